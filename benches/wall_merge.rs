@@ -0,0 +1,68 @@
+//! Benchmarks for merging wall tiles into collider rectangles
+
+// Only the merging itself is used out of the walls module
+#![allow(dead_code)]
+
+use bevy::utils::HashSet;
+use bevy_ecs_ldtk::GridCoords;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+#[path = "../src/physics/walls.rs"]
+mod walls;
+
+use walls::{wall_outline, WallMerge};
+
+/// Roughly half the tiles filled, the same for every run
+fn noise(width: i32, height: i32) -> HashSet<GridCoords> {
+    let mut state: u32 = 0x2545_f491;
+    let mut walls = HashSet::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state & 1 == 0 {
+                walls.insert(GridCoords { x, y });
+            }
+        }
+    }
+
+    walls
+}
+
+/// Rolling hills: solid ground with a staircase surface, the worst case for stacking plates
+fn terrain(width: i32, height: i32) -> HashSet<GridCoords> {
+    (0..width)
+        .flat_map(|x| {
+            let ground = (x % (height / 2).max(1)) + height / 4;
+            (0..ground).map(move |y| GridCoords { x, y })
+        })
+        .collect()
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge_walls");
+
+    for (width, height) in [(64, 64), (256, 256), (1024, 256)] {
+        for (grid, walls) in [("noise", noise(width, height)), ("terrain", terrain(width, height))] {
+            for mode in [WallMerge::Plates, WallMerge::Greedy, WallMerge::Outline] {
+                let id = BenchmarkId::new(format!("{}/{:?}", grid, mode), format!("{}x{}", width, height));
+                group.bench_with_input(id, &walls, |b, walls| {
+                    b.iter(|| match mode {
+                        // The outline is built on top of the greedy rectangles, which the ledges still use
+                        WallMerge::Outline => {
+                            (mode.merge(black_box(walls), width, height), wall_outline(black_box(walls), width, height))
+                        }
+                        _ => (mode.merge(black_box(walls), width, height), Default::default()),
+                    })
+                });
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_merge);
+criterion_main!(benches);
@@ -0,0 +1,345 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+
+use super::{AnimationManifest, AnimationMode, FrameEvent};
+
+/// A clip from the manifest, resolved to indices in a texture atlas
+#[derive(Clone, Debug)]
+pub struct SpriteClip {
+    pub frames: Vec<usize>,
+    pub frame_duration: f32,
+    pub mode: AnimationMode,
+    pub events: Vec<FrameEvent>,
+}
+
+/// Sent when an animation reaches a frame that the manifest tagged
+#[derive(Clone, Debug)]
+pub struct AnimationEvent {
+    pub entity: Entity,
+    pub clip: String,
+    pub frame: usize,
+    pub tag: String,
+}
+
+/// Where an animator's clips came from, so they can be rebuilt when the manifest changes
+#[derive(Clone, Debug)]
+struct AnimationSource {
+    manifest: Handle<AnimationManifest>,
+    frames: Vec<HandleUntyped>,
+}
+
+/// Plays sprite clips on a `TextureAtlasSprite`.
+///
+/// Any entity with a texture atlas can be animated by adding one of these,
+/// gameplay code then only has to call `play` with the name of a clip.
+#[derive(Component, Clone, Debug)]
+pub struct Animator {
+    pub clips: HashMap<String, SpriteClip>,
+    /// Name of the clip that is currently playing
+    pub clip: String,
+    /// Position in the current clip, not the index into the texture atlas
+    pub frame: usize,
+    /// Playback rate, 2.0 plays twice as fast
+    pub speed: f32,
+    pub looping: bool,
+    /// Set once a non-looping clip reaches its last frame
+    pub finished: bool,
+    timer: Timer,
+    // Set when a clip (re)starts, so its first frame is shown without waiting for the timer
+    restarted: bool,
+    // Clips that were asked for but don't exist, so each one is only warned about once
+    missing: HashSet<String>,
+    source: Option<AnimationSource>,
+}
+
+impl Animator {
+    pub fn new(clips: HashMap<String, SpriteClip>, initial_clip: &str) -> Self {
+        let mut animator = Self {
+            clips,
+            clip: String::new(),
+            frame: 0,
+            speed: 1.0,
+            looping: true,
+            finished: false,
+            timer: Timer::from_seconds(0.1, TimerMode::Repeating),
+            restarted: false,
+            missing: HashSet::new(),
+            source: None,
+        };
+        animator.play(initial_clip);
+        animator
+    }
+
+    /// Keeps track of the manifest and frame images the clips were built from,
+    /// so the animator picks up changes to the manifest file.
+    pub fn with_source(mut self, manifest: Handle<AnimationManifest>, frames: Vec<HandleUntyped>) -> Self {
+        self.source = Some(AnimationSource { manifest, frames });
+        self
+    }
+
+    /// Switches to another clip, starting from its first frame.
+    /// Does nothing if the clip is already playing, use `restart` to play it again.
+    pub fn play(&mut self, clip: &str) {
+        if self.clip == clip {
+            return;
+        }
+
+        let Some(sprite_clip) = self.clips.get(clip) else {
+            if self.missing.insert(clip.to_owned()) {
+                warn!("No animation clip named {:?}", clip);
+            }
+            return;
+        };
+
+        self.looping = sprite_clip.mode == AnimationMode::Loop;
+        self.timer.set_duration(Duration::from_secs_f32(sprite_clip.frame_duration));
+        self.clip = clip.to_owned();
+        self.restart();
+    }
+
+    /// Plays the current clip again from its first frame
+    pub fn restart(&mut self) {
+        self.frame = 0;
+        self.finished = false;
+        self.restarted = true;
+        self.timer.reset();
+    }
+
+    pub fn current_clip(&self) -> Option<&SpriteClip> {
+        self.clips.get(&self.clip)
+    }
+
+    /// The texture atlas index of the frame that should be showing
+    pub fn atlas_index(&self) -> Option<usize> {
+        self.current_clip()?.frames.get(self.frame).copied()
+    }
+
+    /// Advances the animation by `delta`, pushing every frame it moved to onto `entered`
+    fn tick(&mut self, delta: Duration, entered: &mut Vec<usize>) {
+        let Some(frame_count) = self.current_clip().map(|clip| clip.frames.len()) else {
+            return;
+        };
+
+        if std::mem::take(&mut self.restarted) {
+            entered.push(self.frame);
+        }
+
+        if self.finished || frame_count == 0 {
+            return;
+        }
+
+        self.timer.tick(delta.mul_f32(self.speed.max(0.)));
+
+        for _ in 0..self.timer.times_finished_this_tick() {
+            if self.frame + 1 < frame_count {
+                self.frame += 1;
+            } else if self.looping {
+                self.frame = 0;
+            } else {
+                // Hold the last frame
+                self.finished = true;
+                break;
+            }
+            entered.push(self.frame);
+        }
+    }
+}
+
+/// Matches every clip in the manifest against the loaded frame images
+/// and looks up where each frame ended up in the texture atlas.
+pub fn resolve_clips(
+    manifest: &AnimationManifest,
+    texture_atlas: &TextureAtlas,
+    handles: &[HandleUntyped],
+    asset_server: &AssetServer,
+) -> HashMap<String, SpriteClip> {
+    let paths: Vec<(String, &HandleUntyped)> = handles
+        .iter()
+        .filter_map(|handle| {
+            let asset_path = asset_server.get_handle_path(handle)?;
+            Some((asset_path.path().to_str()?.to_owned(), handle))
+        })
+        .collect();
+
+    manifest
+        .clips
+        .iter()
+        .filter_map(|(name, clip)| {
+            let mut frames: Vec<(u32, usize)> = paths
+                .iter()
+                .filter_map(|(path, handle)| {
+                    let number = clip.frame_number(path)?;
+                    let index = texture_atlas.get_texture_index(&handle.clone_weak().typed::<Image>())?;
+                    Some((number, index))
+                })
+                .collect();
+
+            if frames.is_empty() {
+                warn!("Animation clip {:?} has no frames matching {:?}", name, clip.frames);
+                return None;
+            }
+
+            frames.sort_by_key(|(number, _)| *number);
+
+            Some((
+                name.clone(),
+                SpriteClip {
+                    frames: frames.into_iter().map(|(_, index)| index).collect(),
+                    frame_duration: manifest.frame_duration(clip),
+                    mode: clip.mode,
+                    events: clip.events.clone(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Stitches a set of loaded frame images together into a single texture atlas
+pub fn build_texture_atlas(
+    handles: &[HandleUntyped],
+    asset_server: &AssetServer,
+    textures: &mut Assets<Image>,
+) -> Option<TextureAtlas> {
+    let mut texture_atlas_builder = TextureAtlasBuilder::default();
+
+    for handle in handles {
+        let handle = handle.typed_weak();
+        let Some(texture) = textures.get(&handle) else {
+            warn!("{:?} did not resolve to an `Image` asset.", asset_server.get_handle_path(handle));
+            continue;
+        };
+
+        texture_atlas_builder.add_texture(handle, texture);
+    }
+
+    texture_atlas_builder.finish(textures).ok()
+}
+
+pub fn animate_sprites(
+    fixed_time: Res<FixedTime>,
+    mut animation_events: EventWriter<AnimationEvent>,
+    mut query: Query<(Entity, &mut Animator, &mut TextureAtlasSprite)>,
+) {
+    let mut entered = Vec::new();
+
+    for (entity, mut animator, mut sprite) in &mut query {
+        entered.clear();
+        animator.tick(fixed_time.period, &mut entered);
+
+        if entered.is_empty() {
+            continue;
+        }
+
+        if let Some(index) = animator.atlas_index() {
+            if sprite.index != index {
+                sprite.index = index;
+            }
+        }
+
+        let Some(clip) = animator.current_clip() else {
+            continue;
+        };
+
+        for &frame in &entered {
+            for event in clip.events.iter().filter(|event| event.frame == frame) {
+                animation_events.send(AnimationEvent {
+                    entity,
+                    clip: animator.clip.clone(),
+                    frame,
+                    tag: event.tag.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Rebuilds the clips of every animator whose manifest changed on disk
+pub fn reload_animations(
+    mut ev_asset: EventReader<AssetEvent<AnimationManifest>>,
+    asset_server: Res<AssetServer>,
+    manifests: Res<Assets<AnimationManifest>>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut query: Query<(&mut Animator, &Handle<TextureAtlas>)>,
+) {
+    for ev in ev_asset.iter() {
+        let AssetEvent::Modified { handle } = ev else {
+            continue;
+        };
+
+        let Some(manifest) = manifests.get(handle) else {
+            continue;
+        };
+
+        for (mut animator, atlas_handle) in &mut query {
+            let Some(frames) = animator
+                .source
+                .as_ref()
+                .filter(|source| source.manifest == *handle)
+                .map(|source| source.frames.clone())
+            else {
+                continue;
+            };
+
+            let Some(texture_atlas) = texture_atlases.get(atlas_handle) else {
+                continue;
+            };
+
+            info!("Reloading animations from {:?}", asset_server.get_handle_path(handle));
+
+            animator.clips = resolve_clips(manifest, texture_atlas, &frames, &asset_server);
+            animator.missing.clear();
+
+            // Restart the current clip with its new timing
+            let clip = std::mem::take(&mut animator.clip);
+            animator.play(&clip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animator() -> Animator {
+        let clip = |mode| SpriteClip { frames: vec![0, 1, 2], frame_duration: 0.1, mode, events: Vec::new() };
+        let clips = [("run".to_string(), clip(AnimationMode::Loop)), ("hurt".to_string(), clip(AnimationMode::Once))];
+        Animator::new(clips.into_iter().collect(), "run")
+    }
+
+    fn run_to_the_end(animator: &mut Animator) {
+        animator.tick(Duration::from_secs(1), &mut Vec::new());
+    }
+
+    #[test]
+    fn playing_the_same_clip_keeps_going() {
+        let mut animator = animator();
+        animator.play("hurt");
+        run_to_the_end(&mut animator);
+        assert!(animator.finished);
+
+        animator.play("hurt");
+        assert!(animator.finished);
+        assert_eq!(animator.frame, 2);
+    }
+
+    #[test]
+    fn restart_plays_a_finished_clip_again() {
+        let mut animator = animator();
+        animator.play("hurt");
+        run_to_the_end(&mut animator);
+
+        animator.restart();
+        assert!(!animator.finished);
+        assert_eq!(animator.frame, 0);
+    }
+
+    #[test]
+    fn missing_clips_keep_the_current_one() {
+        let mut animator = animator();
+        animator.play("nope");
+        animator.play("nope");
+        assert_eq!(animator.clip, "run");
+        assert_eq!(animator.missing.len(), 1);
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::Deserialize;
+
+/// Whether a clip starts over after its last frame, or holds it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Deserialize)]
+pub enum AnimationMode {
+    #[default]
+    Loop,
+    Once,
+}
+
+/// Marks a frame of a clip, an `AnimationEvent` with the tag is sent whenever that frame shows
+#[derive(Clone, Debug, Deserialize)]
+pub struct FrameEvent {
+    /// Position of the frame in the clip, starting at 0
+    pub frame: usize,
+    pub tag: String,
+}
+
+/// A single named clip, as written in the manifest
+#[derive(Clone, Debug, Deserialize)]
+pub struct ClipDefinition {
+    /// File name pattern of the frames, where `*` stands for the frame number.
+    /// "adventurer-run-*" matches "adventurer-run-00.png" to "adventurer-run-05.png"
+    pub frames: String,
+    /// Seconds each frame is shown for, falls back to the manifest's `frame_duration`
+    #[serde(default)]
+    pub frame_duration: Option<f32>,
+    #[serde(default)]
+    pub mode: AnimationMode,
+    #[serde(default)]
+    pub events: Vec<FrameEvent>,
+}
+
+/// A list of animation clips, loaded from a `.anim.ron` file
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "5d0f8c1e-3a41-4c0e-9b7a-8f7f1f2b6e21"]
+pub struct AnimationManifest {
+    pub frame_duration: f32,
+    pub clips: HashMap<String, ClipDefinition>,
+}
+
+impl AnimationManifest {
+    pub fn frame_duration(&self, clip: &ClipDefinition) -> f32 {
+        clip.frame_duration.unwrap_or(self.frame_duration)
+    }
+}
+
+impl ClipDefinition {
+    /// Returns the frame number if `path` is one of this clip's frames
+    pub fn frame_number(&self, path: &str) -> Option<u32> {
+        let file_name = path.rsplit(['/', '\\']).next()?;
+        let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem);
+        let (prefix, suffix) = self.frames.split_once('*')?;
+
+        let number = stem.strip_prefix(prefix)?.strip_suffix(suffix)?;
+
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        number.parse().ok()
+    }
+}
+
+#[derive(Default)]
+pub struct AnimationManifestLoader;
+
+impl AssetLoader for AnimationManifestLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest = ron::de::from_bytes::<AnimationManifest>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(manifest));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim.ron"]
+    }
+}
@@ -0,0 +1,29 @@
+use bevy::prelude::*;
+
+use crate::{AddTickEvent, AppState};
+
+mod animator;
+mod manifest;
+pub use animator::*;
+pub use manifest::*;
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+ fn build(&self, app: &mut App) {
+  app
+    .add_asset::<AnimationManifest>()
+    .init_asset_loader::<AnimationManifestLoader>()
+    .add_tick_event::<AnimationEvent>()
+    .add_system(reload_animations.in_set(OnUpdate(AppState::GameRunning)))
+    .add_system(animate_sprites.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(debug_animation_events.in_set(OnUpdate(AppState::GameRunning)).after(animate_sprites).in_schedule(CoreSchedule::FixedUpdate));
+ }
+}
+
+/// Logs tagged frames at debug level, e.g. with `RUST_LOG=jazz=debug`
+fn debug_animation_events(mut animation_events: EventReader<AnimationEvent>) {
+  for ev in animation_events.iter() {
+    debug!("Animation {:?}: {} frame {} -> {}", ev.entity, ev.clip, ev.frame, ev.tag);
+  }
+}
@@ -0,0 +1,244 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_rapier2d::prelude::*;
+
+use super::Hit;
+
+/// Tuning for what happens to anything that takes damage
+#[derive(Resource, Clone, Debug)]
+pub struct DamageConfig {
+    /// Seconds of invulnerability after taking damage
+    pub invulnerability: f32,
+    /// Seconds between the sprite blinking on and off while invulnerable
+    pub flash_interval: f32,
+}
+
+impl Default for DamageConfig {
+    fn default() -> Self {
+        Self {
+            invulnerability: 1.0,
+            flash_interval: 0.08,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.
+    }
+}
+
+/// Asks for `target` to take damage. Ignored while the target is invulnerable.
+#[derive(Clone, Debug)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub source: Entity,
+    pub amount: f32,
+    /// Change in velocity given to the target, in pixels per second
+    pub knockback: Vec2,
+}
+
+/// Sent after damage was actually dealt, for hurt states and effects
+#[derive(Clone, Debug)]
+pub struct DamageTaken {
+    pub entity: Entity,
+    pub source: Entity,
+    pub amount: f32,
+    /// Whether this took the last of the entity's health
+    pub fatal: bool,
+}
+
+/// Ignores damage until the timer runs out
+#[derive(Component)]
+pub struct Invulnerable {
+    pub timer: Timer,
+    /// Blink the sprite on and off, to show the entity was just hurt
+    pub flashing: bool,
+}
+
+impl Invulnerable {
+    pub fn new(seconds: f32, flashing: bool) -> Self {
+        Self {
+            timer: Timer::from_seconds(seconds, TimerMode::Once),
+            flashing,
+        }
+    }
+}
+
+/// Every hit deals damage to whatever it hit
+pub fn damage_from_hits(mut hits: EventReader<Hit>, mut damage: EventWriter<DamageEvent>) {
+    for hit in hits.iter() {
+        damage.send(DamageEvent {
+            target: hit.target,
+            source: hit.attacker,
+            amount: hit.damage,
+            knockback: hit.knockback,
+        });
+    }
+}
+
+type DamageTarget = (&'static mut Health, Option<&'static mut Velocity>, Option<&'static ReadMassProperties>);
+
+pub fn apply_damage(
+    mut commands: Commands,
+    damage_config: Res<DamageConfig>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut damage_taken: EventWriter<DamageTaken>,
+    mut query: Query<DamageTarget, Without<Invulnerable>>,
+) {
+    // `Invulnerable` only shows up in the query next tick, so later hits this tick are dropped here
+    let mut hit_this_tick = HashSet::new();
+
+    for ev in damage_events.iter() {
+        let Ok((mut health, velocity, mass_properties)) = query.get_mut(ev.target) else {
+            continue;
+        };
+
+        // Already dead, or hit twice this tick
+        if health.is_dead() || !hit_this_tick.insert(ev.target) {
+            continue;
+        }
+
+        health.current = (health.current - ev.amount).max(0.);
+
+        // Knockback replaces whatever the target was doing, as an impulse so Rapier resolves it against walls
+        if let (Some(mut velocity), Some(mass_properties)) = (velocity, mass_properties) {
+            velocity.linvel = Vec2::ZERO;
+            commands.entity(ev.target).insert(ExternalImpulse {
+                impulse: ev.knockback * mass_properties.0.mass,
+                ..default()
+            });
+        }
+
+        commands.entity(ev.target).insert(Invulnerable::new(damage_config.invulnerability, true));
+
+        damage_taken.send(DamageTaken {
+            entity: ev.target,
+            source: ev.source,
+            amount: ev.amount,
+            fatal: health.is_dead(),
+        });
+    }
+}
+
+/// Counts down invulnerability and blinks the sprites of flashing entities
+pub fn tick_invulnerability(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    damage_config: Res<DamageConfig>,
+    mut query: Query<(Entity, &mut Invulnerable, Option<&mut TextureAtlasSprite>, Option<&mut Sprite>)>,
+) {
+    for (entity, mut invulnerable, atlas_sprite, sprite) in &mut query {
+        let finished = invulnerable.timer.tick(fixed_time.period).finished();
+
+        if finished {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+
+        if !invulnerable.flashing {
+            continue;
+        }
+
+        let blink = (invulnerable.timer.elapsed_secs() / damage_config.flash_interval) as u32 % 2 == 1;
+        let alpha = if blink && !finished { 0.2 } else { 1.0 };
+
+        if let Some(mut atlas_sprite) = atlas_sprite {
+            atlas_sprite.color.set_a(alpha);
+        } else if let Some(mut sprite) = sprite {
+            sprite.color.set_a(alpha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn damage_app() -> App {
+        let mut app = App::new();
+        app
+            .init_resource::<DamageConfig>()
+            .add_event::<DamageEvent>()
+            .add_event::<DamageTaken>()
+            .add_system(apply_damage);
+        app
+    }
+
+    fn damage(target: Entity, amount: f32) -> DamageEvent {
+        DamageEvent { target, source: target, amount, knockback: Vec2::ZERO }
+    }
+
+    fn damage_taken(app: &App) -> Vec<DamageTaken> {
+        let events = app.world.resource::<Events<DamageTaken>>();
+        events.get_reader().iter(events).cloned().collect()
+    }
+
+    #[test]
+    fn health_starts_full_and_dies_at_zero() {
+        let mut health = Health::new(3.);
+        assert_eq!(health.current, 3.);
+        assert!(!health.is_dead());
+
+        health.current = 0.;
+        assert!(health.is_dead());
+    }
+
+    #[test]
+    fn damage_takes_health_and_makes_invulnerable() {
+        let mut app = damage_app();
+        let target = app.world.spawn(Health::new(3.)).id();
+
+        app.world.send_event(damage(target, 1.));
+        app.update();
+
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 2.);
+        assert!(app.world.get::<Invulnerable>(target).is_some());
+        let taken = damage_taken(&app);
+        assert_eq!(taken.len(), 1);
+        assert!(!taken[0].fatal);
+
+        // Ignored until the invulnerability runs out
+        app.world.send_event(damage(target, 1.));
+        app.update();
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 2.);
+    }
+
+    #[test]
+    fn two_hits_in_one_tick_only_hurt_once() {
+        let mut app = damage_app();
+        let target = app.world.spawn(Health::new(3.)).id();
+
+        app.world.send_event(damage(target, 1.));
+        app.world.send_event(damage(target, 1.));
+        app.update();
+
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 2.);
+        assert_eq!(damage_taken(&app).len(), 1);
+    }
+
+    #[test]
+    fn health_stops_at_zero_and_the_last_hit_is_fatal() {
+        let mut app = damage_app();
+        let target = app.world.spawn(Health::new(1.)).id();
+        let other = app.world.spawn(Health::new(3.)).id();
+
+        app.world.send_event(damage(target, 5.));
+        app.world.send_event(damage(other, 1.));
+        app.update();
+
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 0.);
+        assert_eq!(app.world.get::<Health>(other).unwrap().current, 2.);
+        let taken = damage_taken(&app);
+        assert_eq!(taken.len(), 2);
+        assert!(taken.iter().any(|taken| taken.entity == target && taken.fatal));
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{AddTickEvent, AppState, SHOW_COLLIDER_BOXES};
+
+mod health;
+mod projectile;
+pub use health::*;
+pub use projectile::*;
+
+/// Marks a collider that hitboxes can hit, the entity it is on is the one that gets hit
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct Hurtbox;
+
+/// A short-lived sensor that hits every hurtbox it touches, once each
+#[derive(Component)]
+pub struct Hitbox {
+    /// Who swung, so attackers don't hit themselves
+    pub owner: Entity,
+    pub damage: f32,
+    /// Push given to whatever is hit, in pixels per second
+    pub knockback: Vec2,
+    pub lifetime: Timer,
+}
+
+/// Sent when a hitbox touches a hurtbox
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub damage: f32,
+    pub knockback: Vec2,
+}
+
+#[derive(Bundle)]
+pub struct HitboxBundle {
+    pub hitbox: Hitbox,
+    pub collider: Collider,
+    pub sensor: Sensor,
+    pub active_events: ActiveEvents,
+    /// Hitboxes are attached to their owner's body, they shouldn't make it any heavier
+    pub mass_properties: ColliderMassProperties,
+    #[bundle]
+    pub transform: TransformBundle,
+}
+
+impl HitboxBundle {
+    /// A hitbox centered `offset` away from its parent
+    pub fn new(hitbox: Hitbox, half_extents: Vec2, offset: Vec2) -> Self {
+        Self {
+            hitbox,
+            collider: Collider::cuboid(half_extents.x, half_extents.y),
+            sensor: Sensor,
+            active_events: ActiveEvents::COLLISION_EVENTS,
+            mass_properties: ColliderMassProperties::Density(0.),
+            transform: TransformBundle::from_transform(Transform::from_translation(offset.extend(0.))),
+        }
+    }
+}
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+ fn build(&self, app: &mut App) {
+  app
+    .init_resource::<DamageConfig>()
+    .add_tick_event::<Hit>()
+    .add_tick_event::<DamageEvent>()
+    .add_tick_event::<DamageTaken>()
+    .add_system(show_hitboxes.in_set(OnUpdate(AppState::GameRunning)))
+    .add_system(detect_hits.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(expire_hitboxes.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(projectile_collisions.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(expire_projectiles.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(debug_hits.in_set(OnUpdate(AppState::GameRunning)).after(detect_hits).after(projectile_collisions).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(damage_from_hits.in_set(OnUpdate(AppState::GameRunning)).after(detect_hits).after(projectile_collisions).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(apply_damage.in_set(OnUpdate(AppState::GameRunning)).after(damage_from_hits).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(tick_invulnerability.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate));
+ }
+}
+
+pub fn detect_hits(
+    mut collisions: EventReader<CollisionEvent>,
+    mut hits: EventWriter<Hit>,
+    hitboxes: Query<&Hitbox>,
+    hurtboxes: Query<With<Hurtbox>>,
+) {
+    for collision_event in collisions.iter() {
+        let CollisionEvent::Started(e1, e2, _) = collision_event else {
+            continue;
+        };
+
+        for (hitbox_entity, target) in [(*e1, *e2), (*e2, *e1)] {
+            let Ok(hitbox) = hitboxes.get(hitbox_entity) else {
+                continue;
+            };
+
+            if target != hitbox.owner && hurtboxes.contains(target) {
+                hits.send(Hit {
+                    attacker: hitbox.owner,
+                    target,
+                    damage: hitbox.damage,
+                    knockback: hitbox.knockback,
+                });
+            }
+        }
+    }
+}
+
+pub fn expire_hitboxes(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    mut query: Query<(Entity, &mut Hitbox)>,
+) {
+    for (entity, mut hitbox) in &mut query {
+        if hitbox.lifetime.tick(fixed_time.period).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn show_hitboxes(mut commands: Commands, query: Query<(Entity, &Collider), Added<Hitbox>>) {
+    if !SHOW_COLLIDER_BOXES {
+        return;
+    }
+
+    for (entity, collider) in &query {
+        let Some(cuboid) = collider.as_cuboid() else {
+            continue;
+        };
+
+        commands.entity(entity).insert(VisibilityBundle::default()).with_children(|hitbox| {
+            hitbox.spawn(SpriteBundle { // Add semi-transparent yellow box to visualize the hitbox
+                sprite: Sprite {
+                    color: Color::rgba(1.0, 1.0, 0., 0.5),
+                    custom_size: Some(cuboid.half_extents() * 2.),
+                    ..default()
+                },
+                ..default()
+            });
+        });
+    }
+}
+
+/// Logs every hit at debug level, e.g. with `RUST_LOG=jazz=debug`
+fn debug_hits(mut hits: EventReader<Hit>) {
+    for hit in hits.iter() {
+        debug!("{:?} hit {:?} for {} damage, knockback {:?}", hit.attacker, hit.target, hit.damage, hit.knockback);
+    }
+}
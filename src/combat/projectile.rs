@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::level::{LevelOutline, Wall};
+
+use super::{Hit, Hurtbox};
+
+/// Something thrown or cast that flies in a straight line until it hits a hurtbox or a wall
+#[derive(Component)]
+pub struct Projectile {
+    /// Who launched it, so casters don't hit themselves
+    pub owner: Entity,
+    pub damage: f32,
+    /// Push given to whatever is hit, in pixels per second
+    pub knockback: Vec2,
+    pub lifetime: Timer,
+}
+
+#[derive(Bundle)]
+pub struct ProjectileBundle {
+    pub projectile: Projectile,
+    #[bundle]
+    pub sprite_bundle: SpriteBundle,
+    pub rigid_body: RigidBody,
+    pub collider: Collider,
+    pub sensor: Sensor,
+    pub velocity: Velocity,
+    pub gravity_scale: GravityScale,
+    pub rotation_constraints: LockedAxes,
+    pub active_events: ActiveEvents,
+}
+
+impl ProjectileBundle {
+    /// A round projectile of `radius` pixels, drawn as a plain colored square
+    pub fn new(projectile: Projectile, position: Vec3, velocity: Vec2, radius: f32, color: Color) -> Self {
+        Self {
+            projectile,
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(radius * 2.)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position),
+                ..default()
+            },
+            rigid_body: RigidBody::Dynamic,
+            collider: Collider::ball(radius),
+            sensor: Sensor,
+            velocity: Velocity::linear(velocity),
+            gravity_scale: GravityScale(0.),
+            rotation_constraints: LockedAxes::ROTATION_LOCKED,
+            active_events: ActiveEvents::COLLISION_EVENTS,
+        }
+    }
+}
+
+/// Wall rectangles, and the outlines that stand in for their colliders in some levels
+type AnyWall = Or<(With<Wall>, With<LevelOutline>)>;
+
+/// Turns projectiles touching hurtboxes into `Hit`s, and stops them at walls
+pub fn projectile_collisions(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    mut hits: EventWriter<Hit>,
+    projectiles: Query<&Projectile>,
+    hurtboxes: Query<With<Hurtbox>>,
+    walls: Query<(), AnyWall>,
+) {
+    for collision_event in collisions.iter() {
+        let CollisionEvent::Started(e1, e2, _) = collision_event else {
+            continue;
+        };
+
+        for (projectile_entity, other) in [(*e1, *e2), (*e2, *e1)] {
+            let Ok(projectile) = projectiles.get(projectile_entity) else {
+                continue;
+            };
+
+            if other != projectile.owner && hurtboxes.contains(other) {
+                hits.send(Hit {
+                    attacker: projectile.owner,
+                    target: other,
+                    damage: projectile.damage,
+                    knockback: projectile.knockback,
+                });
+                commands.entity(projectile_entity).despawn_recursive();
+            } else if walls.contains(other) {
+                commands.entity(projectile_entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+pub fn expire_projectiles(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    mut query: Query<(Entity, &mut Projectile)>,
+) {
+    for (entity, mut projectile) in &mut query {
+        if projectile.lifetime.tick(fixed_time.period).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::{fs, path::PathBuf};
+
+use bevy::{ecs::system::SystemParam, input::InputSystem, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, TickSet};
+
+// How far an axis has to be pushed to count as pressed, after the deadzone
+const PRESS_THRESHOLD: f32 = 0.5;
+
+// Where rebound controls are kept, inside the user's data directory
+const BINDINGS_FILE: &str = "jazz/bindings.ron";
+
+/// Everything the player can do, independent of the key or button it is bound to
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    /// Right is positive
+    MoveX,
+    /// Up is positive
+    MoveY,
+    Jump,
+    Attack,
+    Cast,
+    Dash,
+    /// Draw or sheathe the sword
+    Stance,
+    Pause,
+}
+
+/// A physical key, button or stick
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum InputSource {
+    Key(KeyCode),
+    GamepadButton(GamepadButtonType),
+    GamepadAxis(GamepadAxisType),
+}
+
+/// Ties an input to an action. `scale` is what the input adds to the action when fully pressed,
+/// so A and D can both drive `MoveX` in opposite directions.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Binding {
+    pub action: Action,
+    pub source: InputSource,
+    pub scale: f32,
+}
+
+impl Binding {
+    pub fn new(action: Action, source: InputSource, scale: f32) -> Self {
+        Self { action, source, scale }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+pub struct InputBindings {
+    pub bindings: Vec<Binding>,
+    /// Stick movement below this is ignored, the rest is rescaled to start from zero
+    pub stick_deadzone: f32,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use Action::*;
+        use InputSource::*;
+
+        let bindings = vec![
+            Binding::new(MoveX, Key(KeyCode::D), 1.),
+            Binding::new(MoveX, Key(KeyCode::A), -1.),
+            Binding::new(MoveX, Key(KeyCode::Right), 1.),
+            Binding::new(MoveX, Key(KeyCode::Left), -1.),
+            Binding::new(MoveX, GamepadButton(GamepadButtonType::DPadRight), 1.),
+            Binding::new(MoveX, GamepadButton(GamepadButtonType::DPadLeft), -1.),
+            Binding::new(MoveX, GamepadAxis(GamepadAxisType::LeftStickX), 1.),
+            Binding::new(MoveY, Key(KeyCode::W), 1.),
+            Binding::new(MoveY, Key(KeyCode::S), -1.),
+            Binding::new(MoveY, Key(KeyCode::Up), 1.),
+            Binding::new(MoveY, Key(KeyCode::Down), -1.),
+            Binding::new(MoveY, GamepadButton(GamepadButtonType::DPadUp), 1.),
+            Binding::new(MoveY, GamepadButton(GamepadButtonType::DPadDown), -1.),
+            Binding::new(MoveY, GamepadAxis(GamepadAxisType::LeftStickY), 1.),
+            Binding::new(Jump, Key(KeyCode::Space), 1.),
+            Binding::new(Jump, GamepadButton(GamepadButtonType::South), 1.),
+            Binding::new(Attack, Key(KeyCode::J), 1.),
+            Binding::new(Attack, GamepadButton(GamepadButtonType::West), 1.),
+            Binding::new(Cast, Key(KeyCode::K), 1.),
+            Binding::new(Cast, GamepadButton(GamepadButtonType::North), 1.),
+            Binding::new(Dash, Key(KeyCode::LShift), 1.),
+            Binding::new(Dash, GamepadButton(GamepadButtonType::East), 1.),
+            Binding::new(Dash, GamepadButton(GamepadButtonType::RightTrigger), 1.),
+            Binding::new(Stance, Key(KeyCode::Q), 1.),
+            Binding::new(Stance, GamepadButton(GamepadButtonType::LeftTrigger), 1.),
+            Binding::new(Pause, Key(KeyCode::Escape), 1.),
+            Binding::new(Pause, GamepadButton(GamepadButtonType::Start), 1.),
+        ];
+
+        Self { bindings, stick_deadzone: 0.2 }
+    }
+}
+
+impl InputBindings {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join(BINDINGS_FILE))
+    }
+
+    /// The saved bindings, or the defaults when nothing was saved or the file can't be read
+    pub fn load() -> Self {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Self::default();
+        };
+
+        let bindings = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| ron::from_str(&text).map_err(|err| err.to_string()));
+
+        match bindings {
+            Ok(bindings) => {
+                info!("Loaded input bindings from {}", path.display());
+                bindings
+            }
+            Err(err) => {
+                warn!("Couldn't read input bindings from {}, using the defaults: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = Self::path() else {
+            warn!("No user data directory to save input bindings to");
+            return;
+        };
+
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())
+            .and_then(|text| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+                }
+                fs::write(&path, text).map_err(|err| err.to_string())
+            });
+
+        match result {
+            Ok(()) => info!("Saved input bindings to {}", path.display()),
+            Err(err) => warn!("Couldn't save input bindings to {}: {}", path.display(), err),
+        }
+    }
+
+    /// Every input that drives `action` in the direction of `scale`
+    pub fn sources(&self, action: Action, scale: f32) -> impl Iterator<Item = InputSource> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |binding| binding.action == action && binding.scale == scale)
+            .map(|binding| binding.source)
+    }
+
+    /// Whether `source` opens and closes the pause menu, which can't be rebound
+    pub fn is_reserved(&self, source: InputSource) -> bool {
+        self.sources(Action::Pause, 1.).any(|pause| pause == source)
+    }
+
+    /// Binds `source` to `action`, replacing the action's other keys or buttons of the same kind.
+    /// Sticks are left alone. The source is taken off any other action, so one press never does two things.
+    /// Pause keeps its keys and buttons, returns false if `source` is one of them.
+    pub fn rebind(&mut self, action: Action, scale: f32, source: InputSource) -> bool {
+        if action == Action::Pause || self.is_reserved(source) {
+            return false;
+        }
+
+        self.bindings.retain(|binding| {
+            let replaced = binding.action == action
+                && binding.scale == scale
+                && std::mem::discriminant(&binding.source) == std::mem::discriminant(&source);
+            !replaced && binding.source != source
+        });
+        self.bindings.push(Binding::new(action, source, scale));
+        true
+    }
+}
+
+/// This tick's value of every action, merged from the keyboard and all gamepads.
+/// Gameplay reads this instead of `Input<KeyCode>`.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ActionState {
+    values: HashMap<Action, f32>,
+    previous: HashMap<Action, f32>,
+}
+
+impl ActionState {
+    /// From -1 to 1 for axes, 0 to 1 for buttons
+    pub fn value(&self, action: Action) -> f32 {
+        self.values.get(&action).copied().unwrap_or(0.)
+    }
+
+    /// The value snapped to -1, 0 or 1
+    pub fn direction(&self, action: Action) -> f32 {
+        direction(self.value(action))
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.direction(action) != 0.
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.pressed(action) && direction(self.previous.get(&action).copied().unwrap_or(0.)) == 0.
+    }
+
+    /// Every action that isn't at rest this tick
+    pub fn values(&self) -> impl Iterator<Item = (Action, f32)> + '_ {
+        self.values.iter().filter(|(_, value)| **value != 0.).map(|(action, value)| (*action, *value))
+    }
+
+    /// Replaces this tick's values, keeping the last ones around for `just_pressed`
+    pub fn update(&mut self, values: HashMap<Action, f32>) {
+        self.previous = std::mem::replace(&mut self.values, values);
+    }
+
+    /// Overrides this tick's values after `update`, leaving the last tick alone
+    pub fn replace(&mut self, values: HashMap<Action, f32>) {
+        self.values = values;
+    }
+}
+
+fn direction(value: f32) -> f32 {
+    if value >= PRESS_THRESHOLD {
+        1.
+    } else if value <= -PRESS_THRESHOLD {
+        -1.
+    } else {
+        0.
+    }
+}
+
+/// Ignores small stick movements, and rescales the rest so it still starts from zero
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value.abs() <= deadzone {
+        0.
+    } else {
+        value.signum() * (value.abs() - deadzone) / (1. - deadzone)
+    }
+}
+
+/// Everything `ActionState` is read from
+#[derive(SystemParam)]
+pub struct ActionInputs<'w> {
+    keys: Res<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    bindings: Res<'w, InputBindings>,
+}
+
+impl ActionInputs<'_> {
+    /// The value of every bound action right now
+    fn read(&self) -> HashMap<Action, f32> {
+        let mut values: HashMap<Action, f32> = HashMap::default();
+
+        for binding in &self.bindings.bindings {
+            let value = match binding.source {
+                InputSource::Key(key) => self.keys.pressed(key) as u8 as f32,
+                InputSource::GamepadButton(button_type) => self
+                    .gamepads
+                    .iter()
+                    .any(|gamepad| self.buttons.pressed(GamepadButton::new(gamepad, button_type))) as u8 as f32,
+                InputSource::GamepadAxis(axis_type) => self
+                    .gamepads
+                    .iter()
+                    .filter_map(|gamepad| self.axes.get(GamepadAxis::new(gamepad, axis_type)))
+                    .map(|value| apply_deadzone(value, self.bindings.stick_deadzone))
+                    // Whichever pad is pushed furthest wins
+                    .fold(0., |furthest: f32, value| if value.abs() > furthest.abs() { value } else { furthest }),
+            };
+
+            *values.entry(binding.action).or_default() += value * binding.scale;
+        }
+
+        for value in values.values_mut() {
+            *value = value.clamp(-1., 1.);
+        }
+
+        values
+    }
+}
+
+/// Steps `ActionState` once per tick of the running game, so `just_pressed` lasts exactly one tick
+pub fn update_actions(inputs: ActionInputs, mut actions: ResMut<ActionState>) {
+    actions.update(inputs.read());
+}
+
+/// The actions as of this frame, for menus. They keep working while the game is paused and don't wait for a tick.
+#[derive(Resource, Clone, Debug, Default, Deref)]
+pub struct MenuActions(ActionState);
+
+pub fn update_menu_actions(inputs: ActionInputs, mut menu_actions: ResMut<MenuActions>) {
+    menu_actions.0.update(inputs.read());
+}
+
+/// Where `ActionState` gets filled in, anything overriding it runs after this
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ActionSystem;
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+ fn build(&self, app: &mut App) {
+  app
+    // The defaults, unless the app put saved controls in place before adding the plugin
+    .init_resource::<InputBindings>()
+    .init_resource::<ActionState>()
+    .init_resource::<MenuActions>()
+    .add_system(
+      update_actions
+        .run_if(in_state(AppState::GameRunning))
+        .in_base_set(TickSet::First)
+        .in_set(ActionSystem)
+        .in_schedule(CoreSchedule::FixedUpdate)
+    )
+    .add_system(update_menu_actions.in_base_set(CoreSet::PreUpdate).after(InputSystem));
+ }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(bindings: &InputBindings, action: Action, scale: f32) -> Vec<InputSource> {
+        bindings.sources(action, scale).collect()
+    }
+
+    #[test]
+    fn sources_follow_the_direction() {
+        let bindings = InputBindings::default();
+
+        assert_eq!(
+            sources(&bindings, Action::MoveX, -1.),
+            vec![
+                InputSource::Key(KeyCode::A),
+                InputSource::Key(KeyCode::Left),
+                InputSource::GamepadButton(GamepadButtonType::DPadLeft),
+            ]
+        );
+        assert_eq!(
+            sources(&bindings, Action::Jump, 1.),
+            vec![InputSource::Key(KeyCode::Space), InputSource::GamepadButton(GamepadButtonType::South)]
+        );
+        assert!(sources(&bindings, Action::Jump, -1.).is_empty());
+    }
+
+    #[test]
+    fn rebinding_replaces_the_same_kind_of_input() {
+        let mut bindings = InputBindings::default();
+
+        assert!(bindings.rebind(Action::Jump, 1., InputSource::Key(KeyCode::K)));
+        assert_eq!(
+            sources(&bindings, Action::Jump, 1.),
+            vec![InputSource::GamepadButton(GamepadButtonType::South), InputSource::Key(KeyCode::K)]
+        );
+        // K was Cast's key, it only does one thing now
+        assert_eq!(sources(&bindings, Action::Cast, 1.), vec![InputSource::GamepadButton(GamepadButtonType::North)]);
+    }
+
+    #[test]
+    fn rebinding_keeps_the_other_direction_and_the_stick() {
+        let mut bindings = InputBindings::default();
+
+        assert!(bindings.rebind(Action::MoveX, 1., InputSource::Key(KeyCode::L)));
+        assert_eq!(
+            sources(&bindings, Action::MoveX, 1.),
+            vec![
+                InputSource::GamepadButton(GamepadButtonType::DPadRight),
+                InputSource::GamepadAxis(GamepadAxisType::LeftStickX),
+                InputSource::Key(KeyCode::L),
+            ]
+        );
+        assert_eq!(sources(&bindings, Action::MoveX, -1.).len(), 3);
+    }
+
+    #[test]
+    fn pause_inputs_cannot_be_taken() {
+        let mut bindings = InputBindings::default();
+        let pause = sources(&bindings, Action::Pause, 1.);
+
+        assert!(!bindings.rebind(Action::Jump, 1., InputSource::Key(KeyCode::Escape)));
+        assert!(!bindings.rebind(Action::Attack, 1., InputSource::GamepadButton(GamepadButtonType::Start)));
+        assert!(!bindings.rebind(Action::Pause, 1., InputSource::Key(KeyCode::P)));
+
+        assert_eq!(sources(&bindings, Action::Pause, 1.), pause);
+        assert_eq!(bindings.bindings, InputBindings::default().bindings);
+    }
+}
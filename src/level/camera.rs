@@ -1,4 +1,4 @@
-use bevy::{prelude::*, app::PluginGroupBuilder};
+use bevy::prelude::*;
 use bevy_ecs_ldtk::{LdtkLevel, LevelSelection};
 use crate::{ AppState, player::Player };
 
@@ -20,6 +20,8 @@ fn spawn_camera(mut commands: Commands) {
   commands.spawn(camera);
 }
 
+type LevelOnly = (Without<OrthographicProjection>, Without<Player>);
+
 pub fn camera_fit_inside_current_level(
   mut camera_query: Query<
       (
@@ -29,10 +31,7 @@ pub fn camera_fit_inside_current_level(
       Without<Player>,
   >,
   player_query: Query<&Transform, With<Player>>,
-  level_query: Query<
-      (&Transform, &Handle<LdtkLevel>),
-      (Without<OrthographicProjection>, Without<Player>),
-  >,
+  level_query: Query<(&Transform, &Handle<LdtkLevel>), LevelOnly>,
   level_selection: Res<LevelSelection>,
   ldtk_levels: Res<Assets<LdtkLevel>>,
 ) {
@@ -1,20 +1,22 @@
-use std::collections::HashSet;
-
 use bevy::{prelude::*, app::PluginGroupBuilder};
-use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_ldtk::{prelude::*, ldtk::FieldValue};
 
-use crate::{loading::RegisterAssetEvent, AppState, player::{self, Player}, physics::ColliderBundle};
+use crate::{loading::RegisterAssetEvent, AppState, player::{self, Player, UnlockAbility}, physics::SensorBundle};
 
 mod camera;
 
-const TILE_SIZE: f32 = 32.;
-
+/// A merged wall rectangle, in pixels. Sits on the same entity as the wall's collider,
+/// unless the level's walls are outlined instead
 #[derive(Copy, Clone, Debug, Component)]
 pub struct Wall {
     pub width: f32,
     pub height: f32,
 }
 
+/// The single collider around all of a level's walls, for levels with an `Outline` `WallMerge`
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct LevelOutline;
+
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct WallCell;
@@ -24,6 +26,66 @@ pub struct WallBundle {
     wall: WallCell,
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Ladder;
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct LadderBundle {
+    #[from_int_grid_cell]
+    #[bundle]
+    pub sensor_bundle: SensorBundle,
+    pub ladder: Ladder,
+}
+
+/// Marks where a player respawns after walking through it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Checkpoint;
+
+#[derive(Clone, Default, Bundle, LdtkEntity)]
+pub struct CheckpointBundle {
+    pub checkpoint: Checkpoint,
+    // Keeps the size from the editor
+    #[from_entity_instance]
+    pub entity_instance: EntityInstance,
+}
+
+/// Hands out an ability to the first player who touches it, then disappears
+#[derive(Copy, Clone, Debug, Component)]
+pub struct AbilityPickup(pub UnlockAbility);
+
+impl Default for AbilityPickup {
+    fn default() -> Self {
+        AbilityPickup(UnlockAbility::AirJumps(1))
+    }
+}
+
+impl From<&EntityInstance> for AbilityPickup {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        let air_jumps = entity_instance
+            .field_instances
+            .iter()
+            .find(|field| field.identifier == "AirJumps")
+            .and_then(|field| match field.value {
+                FieldValue::Int(count) => count,
+                _ => None,
+            });
+
+        match air_jumps {
+            Some(count) => AbilityPickup(UnlockAbility::AirJumps(count.max(0) as u32)),
+            None => AbilityPickup::default(),
+        }
+    }
+}
+
+#[derive(Clone, Default, Bundle, LdtkEntity)]
+pub struct AbilityPickupBundle {
+    #[from_entity_instance]
+    pub pickup: AbilityPickup,
+    // Keeps the size from the editor
+    #[from_entity_instance]
+    pub entity_instance: EntityInstance,
+}
+
 pub struct LevelPluginGroup;
 
 impl PluginGroup for LevelPluginGroup {
@@ -56,8 +118,10 @@ impl Plugin for LevelPlugin {
     .add_system(update_level_selection.in_set(OnUpdate(AppState::GameRunning)))
     .register_ldtk_int_cell::<WallBundle>(1)
     .register_ldtk_int_cell::<WallBundle>(2)
-    // .register_ldtk_int_cell::<components::LadderBundle>(3)
-    .register_ldtk_entity::<player::PlayerBundle>("Player");
+    .register_ldtk_int_cell::<LadderBundle>(3)
+    .register_ldtk_entity::<player::PlayerBundle>("Player")
+    .register_ldtk_entity::<CheckpointBundle>("Checkpoint")
+    .register_ldtk_entity::<AbilityPickupBundle>("AbilityPickup");
     // .register_ldtk_entity::<components::MobBundle>("Mob")
     // .register_ldtk_entity::<components::ChestBundle>("Chest")
  }
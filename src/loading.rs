@@ -1,25 +1,16 @@
 
 
-use bevy::{prelude::*, asset::AssetPath};
+use bevy::prelude::*;
 use bevy_ecs_ldtk::LdtkAsset;
 
 use crate::AppState;
 
-#[derive(Resource)]
+#[derive(Resource, Default)]
 struct AssetsLoading {
     assets: Vec<HandleUntyped>,
     did_load_level: bool
 }
 
-impl Default for AssetsLoading {
-    fn default() -> Self {
-      Self {
-        assets: Vec::new(),
-        did_load_level: false
-      }
-    }
-  }
-
 // marker for Loading Screen stuff
 #[derive(Component)]
 struct LoadingScreenComponent;
@@ -86,9 +77,7 @@ fn create_loading_screen(
     // state.set(AppState::LoadingGame).unwrap();
 }
 
-fn destroy_loading_state(
-    mut commands: Commands
-) {
+fn destroy_loading_state() {
     println!("Destroying loading state");
     // commands.remove_resource::<AssetsLoading>();
     // commands.init_resource::<AssetsLoading>();
@@ -152,24 +141,23 @@ fn register_level_loading(
                 }
                 loading.did_load_level = true;
             }
-            AssetEvent::Modified { handle } => {
+            AssetEvent::Modified { .. } => {
                 // an image was modified
             }
-            AssetEvent::Removed { handle } => {
+            AssetEvent::Removed { .. } => {
                 // an image was unloaded
             }
         }
     }
   }
 
-  fn debug_event_too_late(mut ev_reg_image: EventReader<RegisterAssetEvent>, mut loading: ResMut<AssetsLoading>) {
+  fn debug_event_too_late(mut ev_reg_image: EventReader<RegisterAssetEvent>) {
     for ev in ev_reg_image.iter() {
         println!("Event too late: {:?}", ev.label);
     }
   }
 
 fn check_images_ready(
-    mut commands: Commands,
     server: Res<AssetServer>,
     loading: Res<AssetsLoading>,
     mut next_state: ResMut<NextState<AppState>>,
@@ -195,10 +183,8 @@ fn check_images_ready(
                 }
             }
         }
-        LoadState::Loaded => {
-            if(loading.did_load_level) {
-                next_state.set(AppState::GameRunning);
-            }
+        LoadState::Loaded if loading.did_load_level => {
+            next_state.set(AppState::GameRunning);
         }
         _ => {
             // NotLoaded/Loading: not fully ready yet
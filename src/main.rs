@@ -1,11 +1,16 @@
-use bevy::prelude::*;
-use bevy_ecs_ldtk::{LdtkWorldBundle, LdtkPlugin, LevelSelection, LdtkSettings, LevelSpawnBehavior, SetClearColor, LdtkAsset, LdtkSystemSet};
-use bevy_rapier2d::prelude::PhysicsSet;
+use bevy::{ecs::{event::Event, schedule::apply_system_buffers}, prelude::*};
 
+mod animation;
+mod combat;
+mod input;
 mod level;
 mod loading;
 mod player;
 mod physics;
+mod replay;
+mod settings;
+#[cfg(test)]
+mod testing;
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum AppState {
@@ -28,15 +33,27 @@ fn main () {
             ..default()
         }),
         ..default()
-    }).set(ImagePlugin::default_nearest()))
+    }).set(ImagePlugin::default_nearest()).set(AssetPlugin {
+        // Lets animation timings and levels be tweaked while the game runs
+        watch_for_changes: true,
+        ..default()
+    }))
     .add_state::<AppState>()
+    .add_plugin(FixedTickPlugin)
+    // The player's saved controls, read before anything looks at the bindings
+    .insert_resource(input::InputBindings::load())
+    .add_plugin(input::ActionPlugin)
     .add_plugin(DebugStatePlugin)
+    .add_plugin(animation::AnimationPlugin)
     .add_plugins(level::LevelPluginGroup)
     .add_plugins(physics::PhysicsPluginGroup)
-    .configure_set(LdtkSystemSet::ProcessApi.before(PhysicsSet::SyncBackend))
+    .add_plugin(replay::ReplayPlugin::from_args())
     .add_plugin(loading::LoadingPlugin)
     .add_plugin(player::PlayerPlugin)
-    .add_system(bevy::window::close_on_esc)
+    .add_plugin(combat::CombatPlugin)
+    .add_plugin(settings::SettingsPlugin)
+    // Before the settings screen, so Escape cancels a rebind without also unpausing
+    .add_system(toggle_pause.before(settings::SettingsSystem))
     .run();
 }
 
@@ -53,12 +70,30 @@ impl Plugin for DebugStatePlugin {
     }
 }
 
+/// Pause flips between running and paused, it does nothing while loading
+fn toggle_pause(
+  actions: Res<input::MenuActions>,
+  settings_menu: Res<settings::SettingsMenu>,
+  state: Res<State<AppState>>,
+  mut next_state: ResMut<NextState<AppState>>,
+) {
+  if !actions.just_pressed(input::Action::Pause) || settings_menu.capturing() {
+    return;
+  }
+
+  match state.0 {
+    AppState::GameRunning => next_state.set(AppState::GamePaused),
+    AppState::GamePaused => next_state.set(AppState::GameRunning),
+    _ => (),
+  }
+}
+
 fn debug_state_enter (state: Res<State<AppState>>) {
   println!("Entering: {:?}", state.0);
 }
 
 fn  debug_state_update (state: Res<State<AppState>>) {
-  if(state.is_changed()) {
+  if state.is_changed() {
     println!("Running in: {:?}", state.0);
   }
 }
@@ -68,16 +103,57 @@ fn debug_state_exit (state: Res<State<AppState>>) {
 }
 
 // Defines the amount of time that should elapse between each physics step.
-const TIME_STEP: f32 = 1.0;
-
-// fn main() {
-//   App::new()
-//       .add_plugins(DefaultPlugins)
-//       // this system will run once every update (it should match your screen's refresh rate)
-//       .add_system(frame_update)
-//       // add our system to the fixed timestep schedule
-//       .add_system(fixed_update.in_schedule(CoreSchedule::FixedUpdate))
-//       // configure our fixed timestep schedule to run twice a second
-//       .insert_resource(FixedTime::new_from_secs(TIME_STEP))
-//       .run();
-// }
\ No newline at end of file
+pub const TIME_STEP: f32 = 1.0 / 60.0;
+
+/// Stages of one fixed tick, in the order they run. Physics steps between `UpdateFlush` and `PostUpdate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+#[system_set(base)]
+pub enum TickSet {
+    /// Input for the tick is read and events from two ticks ago are dropped
+    First,
+    /// Gameplay, where `OnUpdate` sets go in this schedule
+    Update,
+    UpdateFlush,
+    /// Sees where physics left everything this tick
+    PostUpdate,
+}
+
+/// Runs the simulation on Bevy's fixed timestep: input, gameplay and physics advance in ticks of exactly
+/// `TIME_STEP`, as many per frame as the time that passed calls for. Rendering keeps its own rate.
+/// Systems that step the game go in `CoreSchedule::FixedUpdate` and read `FixedTime` instead of `Time`.
+pub struct FixedTickPlugin;
+
+impl Plugin for FixedTickPlugin {
+    fn build(&self, app: &mut App) {
+      app
+        .insert_resource(FixedTime::new_from_secs(TIME_STEP))
+        .edit_schedule(CoreSchedule::FixedUpdate, |schedule| {
+          schedule
+            .set_default_base_set(TickSet::Update)
+            .configure_sets((TickSet::First, TickSet::Update, TickSet::UpdateFlush, TickSet::PostUpdate).chain());
+
+          // `add_state` only sets these up in the main schedule
+          for state in AppState::variants() {
+            schedule.configure_set(OnUpdate(state).in_base_set(TickSet::Update).run_if(in_state(state)));
+          }
+        })
+        .add_system(apply_system_buffers.in_base_set(TickSet::UpdateFlush).in_schedule(CoreSchedule::FixedUpdate));
+    }
+}
+
+/// Registers events that are sent and read on the fixed tick. `add_event` drops events once a frame,
+/// which loses them when a frame runs no tick, or several.
+pub trait AddTickEvent {
+    fn add_tick_event<T: Event>(&mut self) -> &mut Self;
+}
+
+impl AddTickEvent for App {
+    fn add_tick_event<T: Event>(&mut self) -> &mut Self {
+        if !self.world.contains_resource::<Events<T>>() {
+            self
+                .init_resource::<Events<T>>()
+                .add_system(Events::<T>::update_system.in_base_set(TickSet::First).in_schedule(CoreSchedule::FixedUpdate));
+        }
+        self
+    }
+}
@@ -1,8 +1,10 @@
 use bevy::{prelude::*, utils::{HashSet, HashMap}};
-use bevy_ecs_ldtk::{EntityInstance, GridCoords, LdtkLevel, prelude::LayerInstance, IntGridCell, LdtkIntCell};
+use bevy_ecs_ldtk::{EntityInstance, GridCoords, LdtkLevel, ldtk::{FieldValue, LayerInstance, Level}, IntGridCell, LdtkIntCell};
 use bevy_rapier2d::prelude::*;
 
-use crate::{level::{WallCell}, SHOW_COLLIDER_BOXES};
+use crate::{level::{Ladder, LevelOutline, Wall, WallCell}, SHOW_COLLIDER_BOXES};
+
+use super::{wall_outline, WallMerge};
 
 
 
@@ -17,6 +19,69 @@ pub struct GroundSensor {
     pub intersecting_ground_entities: HashSet<Entity>,
 }
 
+#[derive(Clone, Default, Component)]
+pub struct WallDetection {
+    pub on_wall_left: bool,
+    pub on_wall_right: bool,
+}
+
+impl WallDetection {
+    /// Which side a wall is touching, -1 for left, 1 for right and 0 for none
+    pub fn side(&self) -> i8 {
+        match (self.on_wall_left, self.on_wall_right) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Keeps track of the ladder tiles an entity overlaps
+#[derive(Clone, Default, Component)]
+pub struct ClimbDetection {
+    pub intersecting_ladders: HashSet<Entity>,
+}
+
+impl ClimbDetection {
+    pub fn on_ladder(&self) -> bool {
+        !self.intersecting_ladders.is_empty()
+    }
+}
+
+/// A cuboid collider that can switch to a shorter size for crouching.
+/// Resizing keeps the bottom edge where it is.
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct CrouchCollider {
+    pub standing_half_extents: Vec2,
+    pub crouching_half_extents: Vec2,
+    pub crouched: bool,
+}
+
+impl CrouchCollider {
+    pub fn half_extents(&self) -> Vec2 {
+        if self.crouched { self.crouching_half_extents } else { self.standing_half_extents }
+    }
+
+    /// How far the center of the collider sits below its standing center
+    pub fn center_offset(&self) -> f32 {
+        self.standing_half_extents.y - self.half_extents().y
+    }
+}
+
+/// Whether there is room above a crouching collider to stand up
+#[derive(Clone, Default, Component)]
+pub struct CeilingDetection {
+    pub blocked: bool,
+}
+
+#[derive(Component)]
+pub struct WallSensor {
+    pub wall_detection_entity: Entity,
+    /// -1 for the sensor on the left, 1 for the one on the right
+    pub side: i8,
+    pub intersecting_wall_entities: HashSet<Entity>,
+}
+
 #[derive(Clone, Debug, Default, Bundle)]
 pub struct ColliderBundle {
     pub collider: Collider,
@@ -27,6 +92,8 @@ pub struct ColliderBundle {
     pub gravity_scale: GravityScale,
     pub friction: Friction,
     pub density: ColliderMassProperties,
+    /// Lets knockback be turned into an impulse of the right size
+    pub mass_properties: ReadMassProperties,
 }
 
 #[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
@@ -107,40 +174,160 @@ pub fn update_on_ground(
 }
 
 
+pub fn update_on_wall(
+    mut wall_detectors: Query<&mut WallDetection>,
+    wall_sensors: Query<&WallSensor, Changed<WallSensor>>,
+) {
+    for sensor in &wall_sensors {
+        if let Ok(mut wall_detection) = wall_detectors.get_mut(sensor.wall_detection_entity) {
+            let touching = !sensor.intersecting_wall_entities.is_empty();
+            if sensor.side < 0 {
+                wall_detection.on_wall_left = touching;
+            } else {
+                wall_detection.on_wall_right = touching;
+            }
+        }
+    }
+}
+
+
+pub fn wall_detection(
+    mut wall_sensors: Query<&mut WallSensor>,
+    mut collisions: EventReader<CollisionEvent>,
+    collidables: Query<With<Collider>, Without<Sensor>>,
+) {
+    track_intersections(&mut collisions, |entity| collidables.contains(entity), &mut wall_sensors, |sensor| {
+        &mut sensor.intersecting_wall_entities
+    });
+}
+
+
+pub fn ladder_detection(
+    mut climbers: Query<&mut ClimbDetection>,
+    mut collisions: EventReader<CollisionEvent>,
+    ladders: Query<With<Ladder>>,
+) {
+    track_intersections(&mut collisions, |entity| ladders.contains(entity), &mut climbers, |climber| {
+        &mut climber.intersecting_ladders
+    });
+}
+
+
 pub fn ground_detection(
     mut ground_sensors: Query<&mut GroundSensor>,
     mut collisions: EventReader<CollisionEvent>,
     collidables: Query<With<Collider>, Without<Sensor>>,
+) {
+    track_intersections(&mut collisions, |entity| collidables.contains(entity), &mut ground_sensors, |sensor| {
+        &mut sensor.intersecting_ground_entities
+    });
+}
+
+/// Keeps a set of touched entities up to date from Rapier's collision events.
+/// In every collision between something `touchable` and an entity in `trackers`,
+/// the touchable one is added to or removed from the tracker's set.
+fn track_intersections<T: Component>(
+    collisions: &mut EventReader<CollisionEvent>,
+    touchable: impl Fn(Entity) -> bool,
+    trackers: &mut Query<&mut T>,
+    intersecting: impl Fn(&mut T) -> &mut HashSet<Entity>,
 ) {
     for collision_event in collisions.iter() {
-        match collision_event {
-            CollisionEvent::Started(e1, e2, _) => {
-                if collidables.contains(*e1) {
-                    if let Ok(mut sensor) = ground_sensors.get_mut(*e2) {
-                        sensor.intersecting_ground_entities.insert(*e1);
-                    }
-                } else if collidables.contains(*e2) {
-                    if let Ok(mut sensor) = ground_sensors.get_mut(*e1) {
-                        sensor.intersecting_ground_entities.insert(*e2);
-                    }
-                }
-            }
-            CollisionEvent::Stopped(e1, e2, _) => {
-                if collidables.contains(*e1) {
-                    if let Ok(mut sensor) = ground_sensors.get_mut(*e2) {
-                        sensor.intersecting_ground_entities.remove(e1);
-                    }
-                } else if collidables.contains(*e2) {
-                    if let Ok(mut sensor) = ground_sensors.get_mut(*e1) {
-                        sensor.intersecting_ground_entities.remove(e2);
-                    }
-                }
+        let (e1, e2, started) = match collision_event {
+            CollisionEvent::Started(e1, e2, _) => (*e1, *e2, true),
+            CollisionEvent::Stopped(e1, e2, _) => (*e1, *e2, false),
+        };
+
+        let (touched, tracker) = if touchable(e1) {
+            (e1, e2)
+        } else if touchable(e2) {
+            (e2, e1)
+        } else {
+            continue;
+        };
+
+        if let Ok(mut tracker) = trackers.get_mut(tracker) {
+            let intersecting = intersecting(&mut tracker);
+            if started {
+                intersecting.insert(touched);
+            } else {
+                intersecting.remove(&touched);
             }
         }
     }
 }
 
 
+/// Swaps the collider to the size the `CrouchCollider` asks for,
+/// moving the body so that its bottom edge stays put.
+pub fn resize_crouch_colliders(
+    mut query: Query<(&CrouchCollider, &mut Collider, &mut Transform), Changed<CrouchCollider>>,
+) {
+    for (crouch_collider, mut collider, mut transform) in &mut query {
+        let Some(current) = collider.as_cuboid().map(|cuboid| cuboid.half_extents()) else {
+            continue;
+        };
+
+        let wanted = crouch_collider.half_extents();
+        if current == wanted {
+            continue;
+        }
+
+        *collider = Collider::cuboid(wanted.x, wanted.y);
+        transform.translation.y += wanted.y - current.y;
+    }
+}
+
+/// Checks whether the standing collider would fit where a crouching one is
+pub fn detect_ceiling(
+    rapier_context: Res<RapierContext>,
+    mut query: Query<(Entity, &GlobalTransform, &CrouchCollider, &mut CeilingDetection)>,
+) {
+    for (entity, transform, crouch_collider, mut ceiling_detection) in &mut query {
+        let blocked = crouch_collider.crouched && {
+            let standing = crouch_collider.standing_half_extents;
+            let standing_center = transform.translation().truncate() + Vec2::new(0., crouch_collider.center_offset());
+            // Slightly smaller, so walls and floors we are already touching don't count
+            let shape = Collider::cuboid(standing.x - 1., standing.y - 1.);
+            let filter = QueryFilter::new().exclude_rigid_body(entity).exclude_sensors();
+
+            rapier_context
+                .intersection_with_shape(standing_center, 0., &shape, filter)
+                .is_some()
+        };
+
+        if ceiling_detection.blocked != blocked {
+            ceiling_detection.blocked = blocked;
+        }
+    }
+}
+
+type GroundSensorOnly = (With<Sensor>, Without<WallSensor>);
+type WallSensorOnly = (With<Sensor>, Without<GroundSensor>);
+
+/// Keeps the ground and wall sensors lined up with a collider that changed size
+pub fn fit_sensors_to_collider(
+    colliders: Query<&Collider, (Changed<Collider>, Without<Sensor>)>,
+    mut ground_sensors: Query<(&GroundSensor, &mut Transform, &mut Collider), GroundSensorOnly>,
+    mut wall_sensors: Query<(&WallSensor, &mut Transform, &mut Collider), WallSensorOnly>,
+) {
+    for (sensor, mut transform, mut sensor_shape) in &mut ground_sensors {
+        if let Ok(Some(cuboid)) = colliders.get(sensor.ground_detection_entity).map(|shape| shape.as_cuboid()) {
+            let half_extents = cuboid.half_extents();
+            *sensor_shape = Collider::cuboid(half_extents.x / 2.0, 2.);
+            transform.translation = Vec3::new(0., -half_extents.y, 0.);
+        }
+    }
+
+    for (sensor, mut transform, mut sensor_shape) in &mut wall_sensors {
+        if let Ok(Some(cuboid)) = colliders.get(sensor.wall_detection_entity).map(|shape| shape.as_cuboid()) {
+            let half_extents = cuboid.half_extents();
+            *sensor_shape = Collider::cuboid(2., half_extents.y / 2.);
+            transform.translation = Vec3::new(sensor.side as f32 * half_extents.x, 0., 0.);
+        }
+    }
+}
+
 pub fn spawn_ground_sensor(
   mut commands: Commands,
   detect_ground_for: Query<(Entity, &Collider), Added<GroundDetection>>,
@@ -191,6 +378,75 @@ pub fn spawn_ground_sensor(
   }
 }
 
+/// Spawns a thin sensor on each side of the collider, halfway up,
+/// so that it touches walls but not the floor or the ceiling
+pub fn spawn_wall_sensors(
+  mut commands: Commands,
+  detect_walls_for: Query<(Entity, &Collider), Added<WallDetection>>,
+) {
+  for (entity, shape) in &detect_walls_for {
+      if let Some(cuboid) = shape.as_cuboid() {
+          let Vec2 {
+              x: half_extents_x,
+              y: half_extents_y,
+          } = cuboid.half_extents();
+
+          let detector_shape = Collider::cuboid(2., half_extents_y / 2.);
+
+          commands.entity(entity).with_children(|builder| {
+              for side in [-1, 1] {
+                  let sensor_translation = Vec3::new(side as f32 * half_extents_x, 0., 0.);
+
+                  if SHOW_COLLIDER_BOXES {
+                      builder.spawn(SpriteBundle {
+                          sprite: Sprite {
+                              color: Color::rgba(1.0, 1.0, 0.0, 0.5),
+                              custom_size: Some(Vec2::new(4., half_extents_y)),
+                              ..default()
+                          },
+                          transform: Transform::from_translation(sensor_translation + Vec3::Z * 100.),
+                          ..Default::default()
+                      });
+                  }
+
+                  builder
+                      .spawn_empty()
+                      .insert(ActiveEvents::COLLISION_EVENTS)
+                      .insert(detector_shape.clone())
+                      .insert(Sensor)
+                      .insert(Transform::from_translation(sensor_translation))
+                      .insert(GlobalTransform::default())
+                      .insert(WallSensor {
+                          wall_detection_entity: entity,
+                          side,
+                          intersecting_wall_entities: HashSet::new(),
+                      });
+              }
+          });
+      } else {
+        warn!("Cannot spawn wall sensors for {:?}", entity);
+      }
+  }
+}
+
+/// How a level's walls are merged, picked with an optional `WallMerge` level field in LDtk
+/// holding "Plates", "Greedy" or "Outline". Levels without the field use `WallMerge::default()`.
+fn level_wall_merge(level: &Level) -> WallMerge {
+    let name = level.field_instances.iter().find(|field| field.identifier == "WallMerge").and_then(|field| match &field.value {
+        FieldValue::String(name) | FieldValue::Enum(name) => name.clone(),
+        _ => None,
+    });
+
+    let Some(name) = name else {
+        return WallMerge::default();
+    };
+
+    WallMerge::from_name(&name).unwrap_or_else(|| {
+        warn!("Unknown WallMerge {:?} in level {}, using {:?}", name, level.identifier, WallMerge::default());
+        WallMerge::default()
+    })
+}
+
 /// Spawns heron collisions for the walls of a level
 ///
 /// You could just insert a ColliderBundle in to the WallBundle,
@@ -204,9 +460,8 @@ pub fn spawn_ground_sensor(
 /// and a small number of rectangle colliders.
 /// In basic terms, it will:
 /// 1. consider where the walls are
-/// 2. combine wall tiles into flat "plates" in each individual row
-/// 3. combine the plates into rectangles across multiple rows wherever possible
-/// 4. spawn colliders for each rectangle
+/// 2. merge them into rectangles, the way the level's `WallMerge` field asks for
+/// 3. spawn colliders for each rectangle, or one outline around all of them
 pub fn spawn_wall_collision(
     mut commands: Commands,
     wall_query: Query<(&GridCoords, &Parent), Added<WallCell>>,
@@ -214,22 +469,6 @@ pub fn spawn_wall_collision(
     level_query: Query<(Entity, &Handle<LdtkLevel>)>,
     levels: Res<Assets<LdtkLevel>>,
 ) {
-    /// Represents a wide wall that is 1 tile tall
-    /// Used to spawn wall collisions
-    #[derive(Clone, Eq, PartialEq, Debug, Default, Hash)]
-    struct Plate {
-        left: i32,
-        right: i32,
-    }
-
-    /// A simple rectangle type representing a wall of any size
-    struct Rect {
-        left: i32,
-        right: i32,
-        top: i32,
-        bottom: i32,
-    }
-
     // Consider where the walls are
     // storing them as GridCoords in a HashSet for quick, easy lookup
     //
@@ -270,63 +509,24 @@ pub fn spawn_wall_collision(
                     .clone()
                     .expect("Level asset should have layers")[0];
 
-                // combine wall tiles into flat "plates" in each individual row
-                let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
-
-                for y in 0..height {
-                    let mut row_plates: Vec<Plate> = Vec::new();
-                    let mut plate_start = None;
-
-                    // + 1 to the width so the algorithm "terminates" plates that touch the right edge
-                    for x in 0..width + 1 {
-                        match (plate_start, level_walls.contains(&GridCoords { x, y })) {
-                            (Some(s), false) => {
-                                row_plates.push(Plate {
-                                    left: s,
-                                    right: x - 1,
-                                });
-                                plate_start = None;
-                            }
-                            (None, true) => plate_start = Some(x),
-                            _ => (),
-                        }
-                    }
-
-                    plate_stack.push(row_plates);
-                }
-
-                // combine "plates" into rectangles across multiple rows
-                let mut rect_builder: HashMap<Plate, Rect> = HashMap::new();
-                let mut prev_row: Vec<Plate> = Vec::new();
-                let mut wall_rects: Vec<Rect> = Vec::new();
+                let wall_merge = level_wall_merge(&level.level);
+                let wall_rects = wall_merge.merge(level_walls, width, height);
+                let outline = (wall_merge == WallMerge::Outline).then(|| wall_outline(level_walls, width, height));
 
-                // an extra empty row so the algorithm "finishes" the rects that touch the top edge
-                plate_stack.push(Vec::new());
+                commands.entity(level_entity).with_children(|level| {
+                    if let Some(outline) = outline {
+                        let vertices = outline.vertices.iter().map(|corner| corner.as_vec2() * grid_size as f32).collect();
 
-                for (y, current_row) in plate_stack.into_iter().enumerate() {
-                    for prev_plate in &prev_row {
-                        if !current_row.contains(prev_plate) {
-                            // remove the finished rect so that the same plate in the future starts a new rect
-                            if let Some(rect) = rect_builder.remove(prev_plate) {
-                                wall_rects.push(rect);
-                            }
-                        }
-                    }
-                    for plate in &current_row {
-                        rect_builder
-                            .entry(plate.clone())
-                            .and_modify(|e| e.top += 1)
-                            .or_insert(Rect {
-                                bottom: y as i32,
-                                top: y as i32,
-                                left: plate.left,
-                                right: plate.right,
-                            });
+                        level
+                            .spawn_empty()
+                            .insert(Collider::polyline(vertices, Some(outline.segments)))
+                            .insert(RigidBody::Fixed)
+                            .insert(Friction::new(1.0))
+                            .insert(LevelOutline)
+                            .insert(Transform::default())
+                            .insert(GlobalTransform::default());
                     }
-                    prev_row = current_row;
-                }
 
-                commands.entity(level_entity).with_children(|level| {
                     // Spawn colliders for every rectangle..
                     // Making the collider a child of the level serves two purposes:
                     // 1. Adjusts the transforms to be relative to the level for free
@@ -337,9 +537,9 @@ pub fn spawn_wall_collision(
                             level.spawn(SpriteBundle { // Add semi-transparent blue box to visualize the sensor
                                 sprite: Sprite {
                                     color: Color::rgba(0.0, 1.0, 0., 0.5),
-                                    custom_size: Some(Vec2::new((wall_rect.right as f32 - wall_rect.left as f32 + 1.)
+                                    custom_size: Some(Vec2::new(wall_rect.width() as f32
                                     * grid_size as f32,
-                                    (wall_rect.top as f32 - wall_rect.bottom as f32 + 1.)
+                                    wall_rect.height() as f32
                                     * grid_size as f32)),
                                     ..default()
                                 },
@@ -354,18 +554,27 @@ pub fn spawn_wall_collision(
                             });
                         }
 
-                        level
-                            .spawn_empty()
-                            .insert(Collider::cuboid(
-                                (wall_rect.right as f32 - wall_rect.left as f32 + 1.)
-                                    * grid_size as f32
-                                    / 2.,
-                                (wall_rect.top as f32 - wall_rect.bottom as f32 + 1.)
-                                    * grid_size as f32
-                                    / 2.,
-                            ))
-                            .insert(RigidBody::Fixed)
-                            .insert(Friction::new(1.0))
+                        // Outlined walls keep their rectangles for ledges, without colliding themselves
+                        let mut wall = level.spawn_empty();
+                        if wall_merge != WallMerge::Outline {
+                            wall
+                                .insert(Collider::cuboid(
+                                    wall_rect.width() as f32
+                                        * grid_size as f32
+                                        / 2.,
+                                    wall_rect.height() as f32
+                                        * grid_size as f32
+                                        / 2.,
+                                ))
+                                .insert(RigidBody::Fixed)
+                                .insert(Friction::new(1.0));
+                        }
+
+                        wall
+                            .insert(Wall {
+                                width: wall_rect.width() as f32 * grid_size as f32,
+                                height: wall_rect.height() as f32 * grid_size as f32,
+                            })
                             .insert(Transform::from_xyz(
                                 (wall_rect.left + wall_rect.right + 1) as f32 * grid_size as f32
                                     / 2.,
@@ -380,3 +589,165 @@ pub fn spawn_wall_collision(
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_rapier2d::rapier::geometry::CollisionEventFlags;
+
+    use super::*;
+
+    fn started(e1: Entity, e2: Entity) -> CollisionEvent {
+        CollisionEvent::Started(e1, e2, CollisionEventFlags::SENSOR)
+    }
+
+    fn stopped(e1: Entity, e2: Entity) -> CollisionEvent {
+        CollisionEvent::Stopped(e1, e2, CollisionEventFlags::SENSOR)
+    }
+
+    /// A player with a wall sensor on its right, next to a wall
+    fn wall_app() -> (App, Entity, Entity, Entity) {
+        let mut app = App::new();
+        app
+            .add_event::<CollisionEvent>()
+            .add_systems((wall_detection, update_on_wall).chain());
+
+        let player = app.world.spawn(WallDetection::default()).id();
+        let sensor = app
+            .world
+            .spawn((Collider::cuboid(2., 8.), Sensor, WallSensor {
+                wall_detection_entity: player,
+                side: 1,
+                intersecting_wall_entities: HashSet::new(),
+            }))
+            .id();
+        let wall = app.world.spawn(Collider::cuboid(8., 8.)).id();
+
+        (app, player, sensor, wall)
+    }
+
+    fn wall_side(app: &App, player: Entity) -> i8 {
+        app.world.get::<WallDetection>(player).unwrap().side()
+    }
+
+    #[test]
+    fn wall_sensors_follow_the_walls_they_touch() {
+        let (mut app, player, sensor, wall) = wall_app();
+
+        app.world.send_event(started(sensor, wall));
+        app.update();
+        assert_eq!(wall_side(&app, player), 1);
+
+        // Rapier doesn't say which entity comes first
+        app.world.send_event(stopped(wall, sensor));
+        app.update();
+        assert_eq!(wall_side(&app, player), 0);
+    }
+
+    #[test]
+    fn wall_sensors_ignore_other_sensors() {
+        let (mut app, player, sensor, _) = wall_app();
+        let ladder = app.world.spawn((Collider::cuboid(8., 8.), Sensor)).id();
+
+        app.world.send_event(started(ladder, sensor));
+        app.update();
+        assert_eq!(wall_side(&app, player), 0);
+    }
+
+    #[test]
+    fn climbers_know_when_they_are_on_a_ladder() {
+        let mut app = App::new();
+        app.add_event::<CollisionEvent>().add_system(ladder_detection);
+
+        let player = app.world.spawn((Collider::cuboid(8., 8.), ClimbDetection::default())).id();
+        let ladder = app.world.spawn((Collider::cuboid(8., 8.), Sensor, Ladder)).id();
+        let wall = app.world.spawn(Collider::cuboid(8., 8.)).id();
+        let on_ladder = |app: &App| app.world.get::<ClimbDetection>(player).unwrap().on_ladder();
+
+        app.world.send_event(started(wall, player));
+        app.update();
+        assert!(!on_ladder(&app));
+
+        app.world.send_event(started(player, ladder));
+        app.update();
+        assert!(on_ladder(&app));
+
+        app.world.send_event(stopped(ladder, player));
+        app.update();
+        assert!(!on_ladder(&app));
+    }
+
+    #[test]
+    fn crouching_shrinks_the_collider_and_keeps_the_feet_in_place() {
+        let mut app = App::new();
+        app.add_system(resize_crouch_colliders);
+
+        let crouch_collider = CrouchCollider {
+            standing_half_extents: Vec2::new(8., 16.),
+            crouching_half_extents: Vec2::new(8., 10.),
+            crouched: false,
+        };
+        let player = app
+            .world
+            .spawn((crouch_collider, Collider::cuboid(8., 16.), Transform::from_xyz(0., 100., 0.)))
+            .id();
+        let shape = |app: &App| {
+            let half_extents = app.world.get::<Collider>(player).unwrap().as_cuboid().unwrap().half_extents();
+            let y = app.world.get::<Transform>(player).unwrap().translation.y;
+            (half_extents, y)
+        };
+
+        app.world.get_mut::<CrouchCollider>(player).unwrap().crouched = true;
+        app.update();
+        // Still standing on y = 84
+        assert_eq!(shape(&app), (Vec2::new(8., 10.), 94.));
+        assert_eq!(app.world.get::<CrouchCollider>(player).unwrap().center_offset(), 6.);
+
+        app.world.get_mut::<CrouchCollider>(player).unwrap().crouched = false;
+        app.update();
+        assert_eq!(shape(&app), (Vec2::new(8., 16.), 100.));
+    }
+
+    #[test]
+    fn sensors_follow_a_resized_collider() {
+        let mut app = App::new();
+        app.add_system(fit_sensors_to_collider);
+
+        let player = app.world.spawn(Collider::cuboid(8., 16.)).id();
+        let ground_sensor = app
+            .world
+            .spawn((Collider::cuboid(4., 2.), Sensor, Transform::default(), GroundSensor {
+                ground_detection_entity: player,
+                intersecting_ground_entities: HashSet::new(),
+            }))
+            .id();
+        let wall_sensor = app
+            .world
+            .spawn((Collider::cuboid(2., 8.), Sensor, Transform::default(), WallSensor {
+                wall_detection_entity: player,
+                side: -1,
+                intersecting_wall_entities: HashSet::new(),
+            }))
+            .id();
+
+        *app.world.get_mut::<Collider>(player).unwrap() = Collider::cuboid(8., 10.);
+        app.update();
+
+        assert_eq!(app.world.get::<Transform>(ground_sensor).unwrap().translation, Vec3::new(0., -10., 0.));
+        assert_eq!(app.world.get::<Transform>(wall_sensor).unwrap().translation, Vec3::new(-8., 0., 0.));
+        let wall_shape = app.world.get::<Collider>(wall_sensor).unwrap().as_cuboid().unwrap().half_extents();
+        assert_eq!(wall_shape, Vec2::new(2., 5.));
+    }
+
+    #[test]
+    fn one_wall_leaving_keeps_the_other() {
+        let (mut app, player, sensor, wall) = wall_app();
+        let other_wall = app.world.spawn(Collider::cuboid(8., 8.)).id();
+
+        app.world.send_event(started(sensor, wall));
+        app.world.send_event(started(other_wall, sensor));
+        app.update();
+        app.world.send_event(stopped(sensor, wall));
+        app.update();
+        assert_eq!(wall_side(&app, player), 1);
+    }
+}
@@ -1,17 +1,19 @@
-use bevy::{prelude::*, app::PluginGroupBuilder, utils::HashSet};
-use bevy_ecs_ldtk::EntityInstance;
+use bevy::{prelude::*, app::{PluginGroupBuilder, SystemAppConfigs}};
 use bevy_rapier2d::prelude::*;
 
 mod collision;
+mod walls;
 pub use collision::*;
+pub use walls::*;
 
-use crate::AppState;
+use crate::{AppState, TickSet, TIME_STEP};
 pub struct PhysicsPluginGroup;
 
 impl PluginGroup for PhysicsPluginGroup {
   fn build(self) -> PluginGroupBuilder {
       PluginGroupBuilder::start::<Self>()
-        .add(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+        // The physics systems are added to the fixed tick by `PhysicsPlugin`
+        .add(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0).with_default_system_setup(false))
         .add(PhysicsPlugin)
   }
 }
@@ -24,16 +26,60 @@ impl Plugin for PhysicsPlugin {
   app
     .insert_resource(RapierConfiguration {
         gravity: Vec2::new(0.0, -2000.0),
+        // One step per tick, see `FixedTickPlugin`
+        timestep_mode: TimestepMode::Fixed { dt: TIME_STEP, substeps: 1 },
         ..Default::default()
     })
-    .add_system(update_on_ground.in_set(OnUpdate(AppState::GameRunning)))
-    .add_system(ground_detection.in_set(OnUpdate(AppState::GameRunning)))
-    .add_system(spawn_ground_sensor.in_set(OnUpdate(AppState::GameRunning)))
-    .add_system(spawn_wall_collision.in_set(OnUpdate(AppState::GameRunning)))
+    // Where Rapier puts its sets by default, only between the stages of a tick instead of a frame
+    .edit_schedule(CoreSchedule::FixedUpdate, |schedule| {
+      schedule.configure_sets(
+        (PhysicsSet::SyncBackend, PhysicsSet::SyncBackendFlush, PhysicsSet::StepSimulation, PhysicsSet::Writeback)
+          .chain()
+          .after(TickSet::UpdateFlush)
+          .before(TickSet::PostUpdate),
+      );
+    })
+    .add_systems(physics_systems(PhysicsSet::SyncBackend))
+    .add_systems(physics_systems(PhysicsSet::SyncBackendFlush))
+    .add_systems(physics_systems(PhysicsSet::StepSimulation))
+    .add_systems(physics_systems(PhysicsSet::Writeback))
+    .add_system(update_on_ground.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(ground_detection.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(update_on_wall.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(wall_detection.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(ladder_detection.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(spawn_ground_sensor.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(spawn_wall_sensors.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(resize_crouch_colliders.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(
+      fit_sensors_to_collider
+        .in_set(OnUpdate(AppState::GameRunning))
+        .after(resize_crouch_colliders)
+        .in_schedule(CoreSchedule::FixedUpdate)
+    )
+    .add_system(detect_ceiling.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(spawn_wall_collision.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(pause_physics.in_schedule(OnEnter(AppState::GamePaused)))
+    .add_system(resume_physics.in_schedule(OnExit(AppState::GamePaused)))
     ;
   //   .add_system(spawn_player.in_schedule(OnEnter(AppState::GameRunning)))
   //   .add_system(load_sprites.in_schedule(OnEnter(AppState::GameLoading)))
   //   .add_system(debug_level.in_set(OnUpdate(AppState::GameRunning)));
   //   // .add_system(camera_fit_inside_current_level.in_set(OnUpdate(AppState::GameRunning)));
  }
-}
\ No newline at end of file
+}
+
+fn physics_systems(set: PhysicsSet) -> SystemAppConfigs {
+  RapierPhysicsPlugin::<NoUserData>::get_systems(set.clone())
+    .in_base_set(set)
+    .in_schedule(CoreSchedule::FixedUpdate)
+}
+
+/// Freezes every body in place while the game is paused
+fn pause_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+  rapier_config.physics_pipeline_active = false;
+}
+
+fn resume_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+  rapier_config.physics_pipeline_active = true;
+}
@@ -0,0 +1,486 @@
+use bevy::{
+    math::IVec2,
+    utils::{HashMap, HashSet},
+};
+use bevy_ecs_ldtk::GridCoords;
+
+/// A rectangle of wall tiles in grid coordinates, all edges inclusive
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct WallRect {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
+impl WallRect {
+    /// Width in tiles
+    pub fn width(&self) -> i32 {
+        self.right - self.left + 1
+    }
+
+    /// Height in tiles
+    pub fn height(&self) -> i32 {
+        self.top - self.bottom + 1
+    }
+
+    #[allow(dead_code)] // Only the tests look up single tiles
+    pub fn contains(&self, coords: GridCoords) -> bool {
+        (self.left..=self.right).contains(&coords.x) && (self.bottom..=self.top).contains(&coords.y)
+    }
+}
+
+/// How a level's wall tiles are merged into colliders
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum WallMerge {
+    /// `merge_walls`: only stacks rows with the same edges, so staircases and L-shapes stay split up
+    #[default]
+    Plates,
+    /// `greedy_merge_walls`: grows each rectangle as wide, then as tall as it goes, for fewer colliders
+    Greedy,
+    /// `wall_outline`: a single polyline around all of the level's walls, so there are no edges between
+    /// neighbouring rectangles for the player to catch on. The greedy rectangles are still used for ledges.
+    Outline,
+}
+
+impl WallMerge {
+    /// The mode called `name`, as written in a level's `WallMerge` field
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Plates" => Some(WallMerge::Plates),
+            "Greedy" => Some(WallMerge::Greedy),
+            "Outline" => Some(WallMerge::Outline),
+            _ => None,
+        }
+    }
+
+    /// The rectangles covering the walls. These are the colliders, unless the mode is `Outline`.
+    pub fn merge(self, walls: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<WallRect> {
+        match self {
+            WallMerge::Plates => merge_walls(walls, width, height),
+            WallMerge::Greedy | WallMerge::Outline => greedy_merge_walls(walls, width, height),
+        }
+    }
+
+}
+
+/// Represents a wide wall that is 1 tile tall
+#[derive(Clone, Eq, PartialEq, Debug, Default, Hash)]
+struct Plate {
+    left: i32,
+    right: i32,
+}
+
+/// Covers the wall tiles of a `width` by `height` grid with as few rectangles as this simple approach allows:
+/// 1. combine wall tiles into flat "plates" in each individual row
+/// 2. combine the plates into rectangles across multiple rows wherever possible
+///
+/// Every wall tile ends up in exactly one rectangle. Tiles outside the grid are ignored.
+pub fn merge_walls(walls: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<WallRect> {
+    // combine wall tiles into flat "plates" in each individual row
+    let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
+
+    for y in 0..height {
+        let mut row_plates: Vec<Plate> = Vec::new();
+        let mut plate_start = None;
+
+        // + 1 to the width so the algorithm "terminates" plates that touch the right edge
+        for x in 0..width + 1 {
+            match (plate_start, x < width && walls.contains(&GridCoords { x, y })) {
+                (Some(s), false) => {
+                    row_plates.push(Plate {
+                        left: s,
+                        right: x - 1,
+                    });
+                    plate_start = None;
+                }
+                (None, true) => plate_start = Some(x),
+                _ => (),
+            }
+        }
+
+        plate_stack.push(row_plates);
+    }
+
+    // combine "plates" into rectangles across multiple rows
+    let mut rect_builder: HashMap<Plate, WallRect> = HashMap::new();
+    let mut prev_row: Vec<Plate> = Vec::new();
+    let mut wall_rects: Vec<WallRect> = Vec::new();
+
+    // an extra empty row so the algorithm "finishes" the rects that touch the top edge
+    plate_stack.push(Vec::new());
+
+    for (y, current_row) in plate_stack.into_iter().enumerate() {
+        for prev_plate in &prev_row {
+            if !current_row.contains(prev_plate) {
+                // remove the finished rect so that the same plate in the future starts a new rect
+                if let Some(rect) = rect_builder.remove(prev_plate) {
+                    wall_rects.push(rect);
+                }
+            }
+        }
+        for plate in &current_row {
+            rect_builder
+                .entry(plate.clone())
+                .and_modify(|e| e.top += 1)
+                .or_insert(WallRect {
+                    bottom: y as i32,
+                    top: y as i32,
+                    left: plate.left,
+                    right: plate.right,
+                });
+        }
+        prev_row = current_row;
+    }
+
+    wall_rects
+}
+
+/// Covers the wall tiles of a `width` by `height` grid by greedy meshing:
+/// starting from the lowest, leftmost tile not covered yet, a rectangle takes the whole run of tiles to its right,
+/// then grows upwards for as long as every tile above that run is a free wall.
+///
+/// Unlike `merge_walls`, a row doesn't need the same edges as the one below it to be merged,
+/// so the solid part under a staircase or an L-shape becomes a single rectangle.
+/// Every wall tile ends up in exactly one rectangle. Tiles outside the grid are ignored.
+pub fn greedy_merge_walls(walls: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<WallRect> {
+    if width <= 0 || height <= 0 {
+        return Vec::new();
+    }
+
+    // Wall tiles that no rectangle covers yet, row by row
+    let mut free = vec![false; (width * height) as usize];
+    for coords in walls {
+        if (0..width).contains(&coords.x) && (0..height).contains(&coords.y) {
+            free[(coords.y * width + coords.x) as usize] = true;
+        }
+    }
+    let index = |x: i32, y: i32| (y * width + x) as usize;
+
+    let mut wall_rects = Vec::new();
+
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            if !free[index(x, y)] {
+                x += 1;
+                continue;
+            }
+
+            let left = x;
+            let mut right = x;
+            while right + 1 < width && free[index(right + 1, y)] {
+                right += 1;
+            }
+
+            // Taking the middle out of a wider row above would split it in two, which costs more than it saves
+            let mut top = y;
+            while top + 1 < height && (left..=right).all(|x| free[index(x, top + 1)]) {
+                let wider_left = left > 0 && free[index(left - 1, top + 1)];
+                let wider_right = right + 1 < width && free[index(right + 1, top + 1)];
+                if wider_left && wider_right {
+                    break;
+                }
+                top += 1;
+            }
+
+            for row in y..=top {
+                for x in left..=right {
+                    free[index(x, row)] = false;
+                }
+            }
+
+            wall_rects.push(WallRect { left, right, top, bottom: y });
+            x = right + 1;
+        }
+    }
+
+    wall_rects
+}
+
+/// The edges between wall tiles and open space, in grid corner coordinates.
+/// Every vertex is shared by the segments that meet at it, ready for `Collider::polyline`.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct WallOutline {
+    pub vertices: Vec<IVec2>,
+    pub segments: Vec<[u32; 2]>,
+}
+
+impl WallOutline {
+    fn add_segment(&mut self, indices: &mut HashMap<IVec2, u32>, start: IVec2, end: IVec2) {
+        let mut index = |corner: IVec2| {
+            *indices.entry(corner).or_insert_with(|| {
+                self.vertices.push(corner);
+                self.vertices.len() as u32 - 1
+            })
+        };
+        let segment = [index(start), index(end)];
+        self.segments.push(segment);
+    }
+}
+
+/// Outlines the wall tiles of a `width` by `height` grid.
+/// Edges along the same line are joined for as long as the wall stays on the same side of them,
+/// so every segment ends at a real corner. Tiles outside the grid are ignored.
+pub fn wall_outline(walls: &HashSet<GridCoords>, width: i32, height: i32) -> WallOutline {
+    let is_wall = |x: i32, y: i32| {
+        (0..width).contains(&x) && (0..height).contains(&y) && walls.contains(&GridCoords { x, y })
+    };
+
+    let mut outline = WallOutline::default();
+    let mut indices = HashMap::new();
+
+    // Which side of an edge the wall is on, if only one side has it
+    let wall_side = |before: bool, after: bool| (before != after).then_some(after);
+
+    // Floors and ceilings, along the lines between rows
+    for y in 0..=height {
+        let mut run: Option<(i32, bool)> = None;
+        for x in 0..=width {
+            let side = if x < width { wall_side(is_wall(x, y - 1), is_wall(x, y)) } else { None };
+            if let Some((start, run_side)) = run {
+                if side != Some(run_side) {
+                    outline.add_segment(&mut indices, IVec2::new(start, y), IVec2::new(x, y));
+                    run = None;
+                }
+            }
+            if run.is_none() {
+                run = side.map(|side| (x, side));
+            }
+        }
+    }
+
+    // Sides, along the lines between columns
+    for x in 0..=width {
+        let mut run: Option<(i32, bool)> = None;
+        for y in 0..=height {
+            let side = if y < height { wall_side(is_wall(x - 1, y), is_wall(x, y)) } else { None };
+            if let Some((start, run_side)) = run {
+                if side != Some(run_side) {
+                    outline.add_segment(&mut indices, IVec2::new(x, start), IVec2::new(x, y));
+                    run = None;
+                }
+            }
+            if run.is_none() {
+                run = side.map(|side| (y, side));
+            }
+        }
+    }
+
+    outline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn grid(cells: &[(i32, i32)]) -> HashSet<GridCoords> {
+        cells.iter().map(|&(x, y)| GridCoords { x, y }).collect()
+    }
+
+    /// A grid size, and a random set of wall tiles inside it
+    fn walls() -> impl Strategy<Value = (i32, i32, HashSet<GridCoords>)> {
+        (1..24i32, 1..24i32).prop_flat_map(|(width, height)| {
+            let cells = (width * height) as usize;
+            (Just(width), Just(height), proptest::collection::vec(any::<bool>(), cells)).prop_map(|(width, height, filled)| {
+                let walls = filled
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, filled)| **filled)
+                    .map(|(i, _)| GridCoords { x: i as i32 % width, y: i as i32 / width })
+                    .collect();
+                (width, height, walls)
+            })
+        })
+    }
+
+    #[test]
+    fn empty_grid_has_no_walls() {
+        assert!(merge_walls(&HashSet::new(), 10, 10).is_empty());
+    }
+
+    #[test]
+    fn full_grid_is_one_rect() {
+        let walls = (0..5).flat_map(|x| (0..3).map(move |y| (x, y))).collect::<Vec<_>>();
+        let rects = merge_walls(&grid(&walls), 5, 3);
+        assert_eq!(rects, vec![WallRect { left: 0, right: 4, top: 2, bottom: 0 }]);
+    }
+
+    #[test]
+    fn stacks_plates_with_the_same_edges() {
+        // Two rows of the same plate, then a wider one on top
+        let rects = merge_walls(&grid(&[(1, 0), (2, 0), (1, 1), (2, 1), (0, 2), (1, 2), (2, 2)]), 4, 3);
+        assert_eq!(rects.len(), 2);
+        assert!(rects.contains(&WallRect { left: 1, right: 2, top: 1, bottom: 0 }));
+        assert!(rects.contains(&WallRect { left: 0, right: 2, top: 2, bottom: 2 }));
+    }
+
+    #[test]
+    fn ignores_tiles_outside_the_grid() {
+        let rects = merge_walls(&grid(&[(0, 0), (3, 0), (0, 5)]), 2, 2);
+        assert_eq!(rects, vec![WallRect { left: 0, right: 0, top: 0, bottom: 0 }]);
+    }
+
+    #[test]
+    fn greedy_merges_an_l_shape_into_two_rects() {
+        // A 3 wide floor with a 1 wide pillar on its left end
+        let rects = greedy_merge_walls(&grid(&[(0, 0), (1, 0), (2, 0), (0, 1), (0, 2)]), 3, 3);
+        assert_eq!(rects.len(), 2);
+        assert!(rects.contains(&WallRect { left: 0, right: 2, top: 0, bottom: 0 }));
+        assert!(rects.contains(&WallRect { left: 0, right: 0, top: 2, bottom: 1 }));
+    }
+
+    #[test]
+    fn greedy_needs_fewer_rects_for_a_jagged_wall() {
+        // A 2 wide wall with a tile sticking out to the right on every other row
+        let jagged = (0..8).flat_map(|y| (0..2 + y % 2).map(move |x| (x, y))).collect::<Vec<_>>();
+        let walls = grid(&jagged);
+
+        // No two rows next to each other have the same edges, so plates can't stack at all
+        assert_eq!(merge_walls(&walls, 3, 8).len(), 8);
+        // The wall itself, and the 4 tiles sticking out
+        assert_eq!(greedy_merge_walls(&walls, 3, 8).len(), 5);
+    }
+
+    #[test]
+    fn greedy_keeps_wide_rows_whole() {
+        // A T upside down: one tile under a 3 wide row
+        let rects = greedy_merge_walls(&grid(&[(1, 0), (0, 1), (1, 1), (2, 1)]), 3, 2);
+        assert_eq!(rects.len(), 2);
+        assert!(rects.contains(&WallRect { left: 0, right: 2, top: 1, bottom: 1 }));
+    }
+
+    #[test]
+    fn greedy_full_grid_is_one_rect() {
+        let walls = (0..5).flat_map(|x| (0..3).map(move |y| (x, y))).collect::<Vec<_>>();
+        let rects = greedy_merge_walls(&grid(&walls), 5, 3);
+        assert_eq!(rects, vec![WallRect { left: 0, right: 4, top: 2, bottom: 0 }]);
+    }
+
+    #[test]
+    fn greedy_ignores_tiles_outside_the_grid() {
+        let rects = greedy_merge_walls(&grid(&[(0, 0), (3, 0), (0, 5), (-1, 0)]), 2, 2);
+        assert_eq!(rects, vec![WallRect { left: 0, right: 0, top: 0, bottom: 0 }]);
+    }
+
+    #[test]
+    fn a_single_tile_is_a_square() {
+        let outline = wall_outline(&grid(&[(1, 1)]), 3, 3);
+        assert_eq!(outline.vertices.len(), 4);
+        assert_eq!(outline.segments.len(), 4);
+    }
+
+    #[test]
+    fn outline_joins_the_edges_of_a_floor() {
+        // A 3 wide, 2 tall block is still just 4 sides
+        let walls = (0..3).flat_map(|x| (0..2).map(move |y| (x, y))).collect::<Vec<_>>();
+        let outline = wall_outline(&grid(&walls), 5, 5);
+        assert_eq!(outline.segments.len(), 4);
+    }
+
+    #[test]
+    fn outline_of_an_l_shape_has_six_sides() {
+        let outline = wall_outline(&grid(&[(0, 0), (1, 0), (2, 0), (0, 1), (0, 2)]), 3, 3);
+        assert_eq!(outline.segments.len(), 6);
+        assert_eq!(outline.vertices.len(), 6);
+    }
+
+    #[test]
+    fn outline_ignores_tiles_outside_the_grid() {
+        assert_eq!(wall_outline(&grid(&[(3, 0), (-1, 0)]), 2, 2), WallOutline::default());
+    }
+
+    proptest! {
+        #[test]
+        fn covers_every_wall_exactly_once((width, height, walls) in walls()) {
+            let rects = merge_walls(&walls, width, height);
+
+            for x in 0..width {
+                for y in 0..height {
+                    let coords = GridCoords { x, y };
+                    let covering = rects.iter().filter(|rect| rect.contains(coords)).count();
+                    let expected = if walls.contains(&coords) { 1 } else { 0 };
+                    prop_assert_eq!(covering, expected, "tile {:?} is in {} rects", coords, covering);
+                }
+            }
+        }
+
+        #[test]
+        fn rects_stay_inside_the_grid((width, height, walls) in walls()) {
+            for rect in merge_walls(&walls, width, height) {
+                prop_assert!(rect.left >= 0 && rect.right < width && rect.left <= rect.right);
+                prop_assert!(rect.bottom >= 0 && rect.top < height && rect.bottom <= rect.top);
+            }
+        }
+
+        #[test]
+        fn never_needs_more_rects_than_walls((width, height, walls) in walls()) {
+            prop_assert!(merge_walls(&walls, width, height).len() <= walls.len());
+        }
+
+        #[test]
+        fn greedy_covers_every_wall_exactly_once((width, height, walls) in walls()) {
+            let rects = greedy_merge_walls(&walls, width, height);
+
+            for x in 0..width {
+                for y in 0..height {
+                    let coords = GridCoords { x, y };
+                    let covering = rects.iter().filter(|rect| rect.contains(coords)).count();
+                    let expected = if walls.contains(&coords) { 1 } else { 0 };
+                    prop_assert_eq!(covering, expected, "tile {:?} is in {} rects", coords, covering);
+                }
+            }
+        }
+
+        #[test]
+        fn greedy_rects_stay_inside_the_grid((width, height, walls) in walls()) {
+            for rect in greedy_merge_walls(&walls, width, height) {
+                prop_assert!(rect.left >= 0 && rect.right < width && rect.left <= rect.right);
+                prop_assert!(rect.bottom >= 0 && rect.top < height && rect.bottom <= rect.top);
+            }
+        }
+
+        #[test]
+        fn greedy_never_needs_more_rects_than_plates((width, height, walls) in walls()) {
+            let plates = merge_walls(&walls, width, height).len();
+            let greedy = greedy_merge_walls(&walls, width, height).len();
+            prop_assert!(greedy <= plates, "greedy made {} rects, plates made {}", greedy, plates);
+        }
+
+        #[test]
+        fn outline_runs_along_every_open_tile_edge((width, height, walls) in walls()) {
+            let outline = wall_outline(&walls, width, height);
+            let is_wall = |x: i32, y: i32| (0..width).contains(&x) && (0..height).contains(&y) && walls.contains(&GridCoords { x, y });
+
+            let exposed_edges: i32 = walls
+                .iter()
+                .map(|coords| {
+                    [(1, 0), (-1, 0), (0, 1), (0, -1)].iter().filter(|(dx, dy)| !is_wall(coords.x + dx, coords.y + dy)).count() as i32
+                })
+                .sum();
+            let length: i32 = outline
+                .segments
+                .iter()
+                .map(|[start, end]| {
+                    let (start, end) = (outline.vertices[*start as usize], outline.vertices[*end as usize]);
+                    prop_assert!(start.x == end.x || start.y == end.y, "segment {:?} to {:?} isn't straight", start, end);
+                    Ok((end - start).abs().max_element())
+                })
+                .sum::<Result<_, _>>()?;
+            prop_assert_eq!(length, exposed_edges);
+        }
+
+        #[test]
+        fn outline_is_closed((width, height, walls) in walls()) {
+            let outline = wall_outline(&walls, width, height);
+            let mut ends = vec![0; outline.vertices.len()];
+            for [start, end] in &outline.segments {
+                ends[*start as usize] += 1;
+                ends[*end as usize] += 1;
+            }
+            // Every corner has a floor or ceiling and a side, or two of each where walls touch diagonally
+            prop_assert!(ends.iter().all(|&count| count == 2 || count == 4), "corners with {:?} segments", ends);
+        }
+    }
+}
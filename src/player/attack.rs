@@ -0,0 +1,200 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    animation::AnimationEvent,
+    combat::{Hitbox, HitboxBundle},
+    input::{Action, ActionState},
+};
+
+use super::{Player, PlayerState, PlayerStateChanged};
+
+/// Size, placement and strength of a single attack's hitbox
+#[derive(Clone, Debug)]
+pub struct AttackStats {
+    pub damage: f32,
+    /// Push given to whatever is hit, `x` points away from the player
+    pub knockback: Vec2,
+    pub half_extents: Vec2,
+    /// Center of the hitbox relative to the player, `x` points the way the player faces
+    pub offset: Vec2,
+    /// Seconds the hitbox stays out
+    pub lifetime: f32,
+}
+
+/// Tuning for melee attacks
+#[derive(Resource, Clone, Debug)]
+pub struct AttackConfig {
+    /// Seconds after an attack ends during which another press continues the combo
+    pub combo_window: f32,
+    /// Seconds an attack press is remembered while the player can't attack yet
+    pub input_buffer: f32,
+    /// attack1 to attack3
+    pub ground: [AttackStats; 3],
+    /// air-attack1 and air-attack2
+    pub air: [AttackStats; 2],
+    /// The area hit when a plunge lands
+    pub plunge: AttackStats,
+    /// Speed of the plunge on the way down, in pixels per second
+    pub plunge_speed: f32,
+}
+
+impl Default for AttackConfig {
+    fn default() -> Self {
+        let slash = AttackStats {
+            damage: 1.,
+            knockback: Vec2::new(150., 100.),
+            half_extents: Vec2::new(14., 14.),
+            offset: Vec2::new(24., 0.),
+            lifetime: 0.1,
+        };
+
+        Self {
+            combo_window: 0.3,
+            input_buffer: 0.2,
+            ground: [
+                slash.clone(),
+                slash.clone(),
+                AttackStats { damage: 2., knockback: Vec2::new(300., 200.), half_extents: Vec2::new(18., 16.), ..slash.clone() },
+            ],
+            air: [
+                slash.clone(),
+                AttackStats { offset: Vec2::new(20., -6.), ..slash.clone() },
+            ],
+            plunge: AttackStats {
+                damage: 3.,
+                knockback: Vec2::new(0., 300.),
+                half_extents: Vec2::new(40., 12.),
+                offset: Vec2::new(0., -8.),
+                ..slash
+            },
+            plunge_speed: 700.,
+        }
+    }
+}
+
+impl AttackConfig {
+    pub fn stats(&self, state: PlayerState) -> Option<&AttackStats> {
+        match state {
+            PlayerState::Attack1 => Some(&self.ground[0]),
+            PlayerState::Attack2 => Some(&self.ground[1]),
+            PlayerState::Attack3 => Some(&self.ground[2]),
+            PlayerState::AirAttack1 => Some(&self.air[0]),
+            PlayerState::AirAttack2 => Some(&self.air[1]),
+            PlayerState::PlungeLanding => Some(&self.plunge),
+            _ => None,
+        }
+    }
+}
+
+/// Per player combo bookkeeping, the timers count down to zero
+#[derive(Clone, Debug, Default, Component)]
+pub struct ComboController {
+    /// Time left before a buffered attack press is dropped
+    pub buffer_timer: f32,
+    /// Ground attacks chained so far, 0 when the combo has run out
+    pub chain: u8,
+    /// Time left to continue the combo after the last attack ended
+    pub window_timer: f32,
+}
+
+/// Buffers attack presses and runs the combo window, the state machine does the rest
+pub fn read_attack_input(
+    actions: Res<ActionState>,
+    fixed_time: Res<FixedTime>,
+    attack_config: Res<AttackConfig>,
+    mut query: Query<(&Player, &mut ComboController)>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+
+    for (player, mut combo) in &mut query {
+        let attacking = player.state.is_attack();
+
+        if actions.just_pressed(Action::Attack) {
+            combo.buffer_timer = attack_config.input_buffer;
+        } else if !attacking {
+            // Presses made during an attack are held on to until it ends
+            combo.buffer_timer = (combo.buffer_timer - delta).max(0.);
+        }
+
+        if attacking {
+            combo.window_timer = attack_config.combo_window;
+        } else {
+            combo.window_timer = (combo.window_timer - delta).max(0.);
+            if combo.window_timer == 0. {
+                combo.chain = 0;
+            }
+        }
+    }
+}
+
+/// Uses up the buffered press once an attack starts and counts the combo
+pub fn track_combo(
+    mut state_changed: EventReader<PlayerStateChanged>,
+    mut query: Query<&mut ComboController>,
+) {
+    for ev in state_changed.iter() {
+        let Ok(mut combo) = query.get_mut(ev.entity) else {
+            continue;
+        };
+
+        if ev.to.is_attack() {
+            combo.buffer_timer = 0.;
+        }
+
+        combo.chain = match ev.to {
+            PlayerState::Attack1 => 1,
+            PlayerState::Attack2 => 2,
+            PlayerState::Attack3 => 3,
+            _ => combo.chain,
+        };
+    }
+}
+
+/// Holds the player still while winding up a plunge, then drives it straight down.
+/// Runs after `movement`, which leaves plunging players alone.
+pub fn plunge(
+    attack_config: Res<AttackConfig>,
+    mut query: Query<(&Player, &mut Velocity, &mut GravityScale)>,
+) {
+    for (player, mut velocity, mut gravity_scale) in &mut query {
+        match player.state {
+            PlayerState::PlungeReady => velocity.linvel = Vec2::ZERO,
+            PlayerState::PlungeFalling => velocity.linvel = Vec2::new(0., -attack_config.plunge_speed),
+            _ => continue,
+        }
+        gravity_scale.0 = 0.;
+    }
+}
+
+/// Puts a hitbox in front of the player on the frames the manifest tags with "hitbox"
+pub fn spawn_attack_hitboxes(
+    mut commands: Commands,
+    mut animation_events: EventReader<AnimationEvent>,
+    attack_config: Res<AttackConfig>,
+    players: Query<&Player>,
+) {
+    for ev in animation_events.iter().filter(|ev| ev.tag == "hitbox") {
+        let Ok(player) = players.get(ev.entity) else {
+            continue;
+        };
+
+        let Some(stats) = attack_config.stats(player.state) else {
+            continue;
+        };
+
+        let facing = if player.facing < 0 { -1. } else { 1. };
+        let flip = Vec2::new(facing, 1.);
+
+        let hitbox = Hitbox {
+            owner: ev.entity,
+            damage: stats.damage,
+            knockback: stats.knockback * flip,
+            lifetime: Timer::from_seconds(stats.lifetime, TimerMode::Once),
+        };
+
+        commands.entity(ev.entity).with_children(|player| {
+            player.spawn(HitboxBundle::new(hitbox, stats.half_extents, stats.offset * flip));
+        });
+    }
+}
@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+
+use crate::combat::{Projectile, ProjectileBundle};
+
+use super::{Player, PlayerState, PlayerStateChanged};
+
+/// Tuning for the spell the player casts. Values scale from `min` to `max` as the cast charges.
+#[derive(Resource, Clone, Debug)]
+pub struct CastConfig {
+    /// Seconds of holding the button until the spell is fully charged
+    pub charge_time: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub min_damage: f32,
+    pub max_damage: f32,
+    pub min_radius: f32,
+    pub max_radius: f32,
+    /// Push given to whatever is hit, `x` points the way the spell flies
+    pub knockback: Vec2,
+    /// Seconds before a spell that hit nothing fizzles out
+    pub lifetime: f32,
+    /// Where the spell appears relative to the player, `x` points the way the player faces
+    pub offset: Vec2,
+}
+
+impl Default for CastConfig {
+    fn default() -> Self {
+        Self {
+            charge_time: 1.,
+            min_speed: 300.,
+            max_speed: 600.,
+            min_damage: 1.,
+            max_damage: 3.,
+            min_radius: 4.,
+            max_radius: 8.,
+            knockback: Vec2::new(200., 100.),
+            lifetime: 1.5,
+            offset: Vec2::new(20., 4.),
+        }
+    }
+}
+
+/// How long the current cast has been charging, in seconds
+#[derive(Clone, Debug, Default, Component)]
+pub struct CastController {
+    pub charge: f32,
+}
+
+pub fn charge_cast(fixed_time: Res<FixedTime>, mut query: Query<(&Player, &mut CastController)>) {
+    for (player, mut cast) in &mut query {
+        if player.state.is_casting() {
+            cast.charge += fixed_time.period.as_secs_f32();
+        }
+    }
+}
+
+/// Launches the spell once the player stops casting, unless something interrupted the cast
+pub fn launch_spells(
+    mut commands: Commands,
+    mut state_changed: EventReader<PlayerStateChanged>,
+    cast_config: Res<CastConfig>,
+    mut query: Query<(&Player, &Transform, &mut CastController)>,
+) {
+    for ev in state_changed.iter() {
+        let Ok((player, transform, mut cast)) = query.get_mut(ev.entity) else {
+            continue;
+        };
+
+        if ev.to == PlayerState::Casting {
+            cast.charge = 0.;
+        }
+
+        let interrupted = matches!(ev.to, PlayerState::Hurt | PlayerState::Dead);
+        if !ev.from.is_casting() || ev.to.is_casting() || interrupted {
+            continue;
+        }
+
+        let charge = (cast.charge / cast_config.charge_time).clamp(0., 1.);
+        let facing = if player.facing < 0 { -1. } else { 1. };
+        let flip = Vec2::new(facing, 1.);
+
+        let projectile = Projectile {
+            owner: ev.entity,
+            damage: lerp(cast_config.min_damage, cast_config.max_damage, charge),
+            knockback: cast_config.knockback * flip,
+            lifetime: Timer::from_seconds(cast_config.lifetime, TimerMode::Once),
+        };
+        let position = transform.translation + (cast_config.offset * flip).extend(0.);
+        let velocity = Vec2::new(facing * lerp(cast_config.min_speed, cast_config.max_speed, charge), 0.);
+        let radius = lerp(cast_config.min_radius, cast_config.max_radius, charge);
+
+        debug!("Cast a spell at {:.0}% charge", charge * 100.);
+        commands.spawn(ProjectileBundle::new(projectile, position, velocity, radius, Color::rgb(0.4, 0.7, 1.0)));
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
@@ -0,0 +1,204 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    combat::Invulnerable,
+    input::{Action, ActionState},
+    physics::GroundDetection,
+};
+
+use super::{Player, PlayerState, PlayerStateRequest};
+
+/// Tuning for the dash
+#[derive(Resource, Clone, Debug)]
+pub struct DashConfig {
+    /// Speed while dashing, in pixels per second
+    pub speed: f32,
+    /// Seconds a dash lasts
+    pub duration: f32,
+    /// Seconds from the start of one dash until the next one is allowed
+    pub cooldown: f32,
+    /// Speed the player keeps once the dash is over
+    pub exit_speed: f32,
+    /// Dash in any of eight directions instead of only the way the player is facing
+    pub eight_way: bool,
+    /// Ignore damage for the length of the dash
+    pub invulnerable: bool,
+    /// Seconds between ghost images left behind while dashing
+    pub ghost_interval: f32,
+    /// Seconds a ghost image takes to fade away
+    pub ghost_lifetime: f32,
+}
+
+impl Default for DashConfig {
+    fn default() -> Self {
+        Self {
+            speed: 600.,
+            duration: 0.15,
+            cooldown: 0.5,
+            exit_speed: 250.,
+            eight_way: false,
+            invulnerable: true,
+            ghost_interval: 0.03,
+            ghost_lifetime: 0.25,
+        }
+    }
+}
+
+/// Per player dash bookkeeping, the timers count down to zero
+#[derive(Clone, Debug, Default, Component)]
+pub struct DashController {
+    pub direction: Vec2,
+    /// Time left in the current dash, zero when not dashing
+    pub dash_timer: f32,
+    /// Time left before the player can dash again
+    pub cooldown_timer: f32,
+    /// Set once the player dashed in the air, cleared on landing
+    pub air_dash_used: bool,
+    ghost_timer: f32,
+}
+
+impl DashController {
+    pub fn dashing(&self) -> bool {
+        self.dash_timer > 0.
+    }
+}
+
+/// A fading copy of the player's sprite, left behind while dashing
+#[derive(Component)]
+pub struct DashGhost {
+    timer: Timer,
+    alpha: f32,
+}
+
+fn can_dash(state: PlayerState) -> bool {
+    matches!(
+        state,
+        PlayerState::Idle
+            | PlayerState::Walking
+            | PlayerState::Jumping
+            | PlayerState::Somersault
+            | PlayerState::Falling
+            | PlayerState::Landing
+            | PlayerState::WallJumping
+    )
+}
+
+type Dasher = (
+    Entity,
+    &'static mut Player,
+    &'static mut DashController,
+    &'static mut Velocity,
+    &'static mut GravityScale,
+    &'static GroundDetection,
+    &'static Transform,
+    Option<&'static Invulnerable>,
+    Option<(&'static TextureAtlasSprite, &'static Handle<TextureAtlas>)>,
+);
+
+/// Starts, drives and ends dashes. Runs after `movement`, which leaves dashing players alone.
+pub fn dash(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    fixed_time: Res<FixedTime>,
+    dash_config: Res<DashConfig>,
+    mut requests: EventWriter<PlayerStateRequest>,
+    mut query: Query<Dasher>,
+) {
+    let delta = fixed_time.period.as_secs_f32();
+
+    for (entity, mut player, mut dash, mut velocity, mut gravity_scale, ground_detection, transform, invulnerable, sprite) in &mut query {
+        dash.cooldown_timer = (dash.cooldown_timer - delta).max(0.);
+
+        let on_ground = ground_detection.on_ground && velocity.linvel.y <= 0.;
+        if on_ground && !dash.dashing() {
+            dash.air_dash_used = false;
+        }
+
+        if !dash.dashing() {
+            let wants_dash = actions.just_pressed(Action::Dash)
+                && dash.cooldown_timer == 0.
+                && (on_ground || !dash.air_dash_used)
+                && can_dash(player.state);
+
+            if !wants_dash {
+                continue;
+            }
+
+            let held = Vec2::new(
+                actions.direction(Action::MoveX),
+                if dash_config.eight_way { actions.direction(Action::MoveY) } else { 0. },
+            );
+            // Without a direction held, dash the way the player is facing
+            dash.direction = if held == Vec2::ZERO { Vec2::new(player.facing as f32, 0.) } else { held.normalize() };
+            if dash.direction.x != 0. {
+                player.facing = dash.direction.x.signum() as i8;
+            }
+
+            dash.dash_timer = dash_config.duration;
+            dash.cooldown_timer = dash_config.cooldown;
+            dash.ghost_timer = 0.;
+            dash.air_dash_used |= !on_ground;
+
+            // Don't cut short the invulnerability from being hurt
+            if dash_config.invulnerable && invulnerable.is_none() {
+                commands.entity(entity).insert(Invulnerable::new(dash_config.duration, false));
+            }
+
+            requests.send(PlayerStateRequest { entity, state: PlayerState::Dash });
+        } else if matches!(player.state, PlayerState::Hurt | PlayerState::Dead) {
+            // Cut short by something else
+            dash.dash_timer = 0.;
+            continue;
+        }
+
+        dash.dash_timer = (dash.dash_timer - delta).max(0.);
+
+        if dash.dashing() {
+            velocity.linvel = dash.direction * dash_config.speed;
+            gravity_scale.0 = 0.;
+        } else {
+            velocity.linvel = dash.direction * dash_config.exit_speed;
+            gravity_scale.0 = 1.;
+            let state = if on_ground { PlayerState::Idle } else { PlayerState::Falling };
+            requests.send(PlayerStateRequest { entity, state });
+        }
+
+        dash.ghost_timer -= delta;
+        if let Some((sprite, atlas)) = sprite.filter(|_| dash.ghost_timer <= 0.) {
+            dash.ghost_timer = dash_config.ghost_interval;
+            spawn_ghost(&mut commands, sprite, atlas, transform, dash_config.ghost_lifetime);
+        }
+    }
+}
+
+fn spawn_ghost(commands: &mut Commands, sprite: &TextureAtlasSprite, atlas: &Handle<TextureAtlas>, transform: &Transform, lifetime: f32) {
+    let alpha = 0.5;
+    let mut ghost_sprite = sprite.clone();
+    ghost_sprite.color = Color::rgba(0.6, 0.8, 1.0, alpha);
+
+    commands.spawn((
+        SpriteSheetBundle {
+            sprite: ghost_sprite,
+            texture_atlas: atlas.clone(),
+            // Just behind the player
+            transform: transform.with_translation(transform.translation - Vec3::Z * 0.1),
+            ..default()
+        },
+        DashGhost { timer: Timer::from_seconds(lifetime, TimerMode::Once), alpha },
+    ));
+}
+
+pub fn fade_dash_ghosts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut DashGhost, &mut TextureAtlasSprite)>,
+) {
+    for (entity, mut ghost, mut sprite) in &mut query {
+        if ghost.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            sprite.color.set_a(ghost.alpha * ghost.timer.percent_left());
+        }
+    }
+}
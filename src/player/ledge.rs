@@ -0,0 +1,245 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    animation::Animator,
+    input::{Action, ActionState},
+    level::Wall,
+    physics::WallDetection,
+};
+
+use super::{JumpConfig, Player, PlayerState, PlayerStateRequest};
+
+// How far below the top of the player's collider a corner can be and still be grabbed.
+// Has to cover the distance fallen in a single frame.
+const GRAB_REACH: f32 = 14.;
+
+// How far the top of the collider pokes over the corner while hanging
+const HANG_OFFSET: f32 = 6.;
+
+// Seconds before a ledge can be grabbed again after letting go
+const REGRAB_COOLDOWN: f32 = 0.3;
+
+/// The corner a player is hanging from, in world space
+#[derive(Copy, Clone, Debug, Component)]
+pub struct LedgeGrab {
+    pub corner: Vec2,
+    /// Side of the player the wall is on, -1 for left, 1 for right
+    pub side: i8,
+}
+
+/// Stops a player that just let go of a ledge from grabbing it again straight away
+#[derive(Component, Deref, DerefMut)]
+pub struct LedgeCooldown(Timer);
+
+fn half_extents(collider: &Collider) -> Vec2 {
+    collider.as_cuboid().map_or(Vec2::splat(16.), |cuboid| cuboid.half_extents())
+}
+
+/// Moves an entity so its global position ends up at `target`, whatever its parent is
+pub(super) fn move_to(transform: &mut Transform, global_transform: &GlobalTransform, target: Vec2) {
+    let delta = target - global_transform.translation().truncate();
+    transform.translation += delta.extend(0.);
+}
+
+/// Finds the top corner of a wall rectangle next to the player's hands.
+/// The corner only counts if no other rectangle sits on top of it.
+fn find_corner(walls: &[(Vec2, Vec2)], hands: Vec2, side: i8) -> Option<Vec2> {
+    walls.iter().find_map(|(center, half_size)| {
+        let corner = Vec2::new(center.x - side as f32 * half_size.x, center.y + half_size.y);
+
+        let within_reach = (corner.x - hands.x).abs() <= 4.
+            && corner.y <= hands.y
+            && corner.y >= hands.y - GRAB_REACH;
+
+        if !within_reach {
+            return None;
+        }
+
+        // Just above the corner, on the wall side, has to be free to climb on to
+        let above = corner + Vec2::new(side as f32, 1.);
+        let blocked = walls.iter().any(|(center, half_size)| {
+            (above.x - center.x).abs() < half_size.x && (above.y - center.y).abs() < half_size.y
+        });
+
+        (!blocked).then_some(corner)
+    })
+}
+
+type LedgeGrabber = (
+    Entity,
+    &'static Player,
+    &'static mut Transform,
+    &'static GlobalTransform,
+    &'static mut Velocity,
+    &'static mut GravityScale,
+    &'static Collider,
+    &'static WallDetection,
+);
+
+pub fn grab_ledges(
+    mut commands: Commands,
+    mut requests: EventWriter<PlayerStateRequest>,
+    mut players: Query<LedgeGrabber, (Without<LedgeGrab>, Without<LedgeCooldown>)>,
+    walls: Query<(&Wall, &GlobalTransform)>,
+) {
+    let wall_rects: Vec<(Vec2, Vec2)> = walls
+        .iter()
+        .map(|(wall, transform)| (transform.translation().truncate(), Vec2::new(wall.width, wall.height) / 2.))
+        .collect();
+
+    for (entity, player, mut transform, global_transform, mut velocity, mut gravity_scale, collider, wall_detection) in &mut players {
+        let falling = matches!(player.state, PlayerState::Falling | PlayerState::WallSliding);
+        let side = wall_detection.side();
+
+        if !falling || side == 0 || velocity.linvel.y > 0. {
+            continue;
+        }
+
+        let half_extents = half_extents(collider);
+        let position = global_transform.translation().truncate();
+        let hands = position + Vec2::new(side as f32 * half_extents.x, half_extents.y);
+
+        let Some(corner) = find_corner(&wall_rects, hands, side) else {
+            continue;
+        };
+
+        // Snap to the hanging position and hold still
+        let hang_position = Vec2::new(
+            corner.x - side as f32 * half_extents.x,
+            corner.y - half_extents.y + HANG_OFFSET,
+        );
+        move_to(&mut transform, global_transform, hang_position);
+        velocity.linvel = Vec2::ZERO;
+        gravity_scale.0 = 0.;
+
+        commands.entity(entity).insert(LedgeGrab { corner, side });
+        requests.send(PlayerStateRequest { entity, state: PlayerState::LedgeHanging });
+    }
+}
+
+type LedgeHanger = (
+    Entity,
+    &'static mut Player,
+    &'static mut Transform,
+    &'static GlobalTransform,
+    &'static mut Velocity,
+    &'static mut GravityScale,
+    &'static Collider,
+    &'static LedgeGrab,
+    Option<&'static Animator>,
+);
+
+/// Climbs up, jumps off or drops down from a grabbed ledge
+pub fn hang_from_ledges(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    jump_config: Res<JumpConfig>,
+    mut requests: EventWriter<PlayerStateRequest>,
+    mut players: Query<LedgeHanger>,
+) {
+    for (entity, mut player, mut transform, global_transform, mut velocity, mut gravity_scale, collider, grab, animator) in &mut players {
+        let side = grab.side as f32;
+        let let_go = |commands: &mut Commands, gravity_scale: &mut GravityScale| {
+            gravity_scale.0 = 1.;
+            commands
+                .entity(entity)
+                .remove::<LedgeGrab>()
+                .insert(LedgeCooldown(Timer::from_seconds(REGRAB_COOLDOWN, TimerMode::Once)));
+        };
+
+        match player.state {
+            PlayerState::LedgeHanging => {
+                velocity.linvel = Vec2::ZERO;
+
+                let pushed_y = if actions.just_pressed(Action::MoveY) { actions.direction(Action::MoveY) } else { 0. };
+
+                if pushed_y > 0. {
+                    requests.send(PlayerStateRequest { entity, state: PlayerState::LedgeClimbing });
+                } else if actions.just_pressed(Action::Jump) {
+                    velocity.linvel = Vec2::new(-side * jump_config.wall_jump_velocity.x, jump_config.jump_velocity);
+                    player.facing = -grab.side;
+                    let_go(&mut commands, &mut gravity_scale);
+                    requests.send(PlayerStateRequest { entity, state: PlayerState::WallJumping });
+                } else if pushed_y < 0. {
+                    let_go(&mut commands, &mut gravity_scale);
+                    requests.send(PlayerStateRequest { entity, state: PlayerState::Falling });
+                }
+            }
+            PlayerState::LedgeClimbing => {
+                velocity.linvel = Vec2::ZERO;
+
+                // The hanging clip is still finished on the frame the climb starts, so only the climb's own end counts.
+                // Without a climb clip to wait for, climb straight away.
+                let climb = PlayerState::LedgeClimbing.animation();
+                let climbed = animator.is_none_or(|animator| {
+                    !animator.clips.contains_key(climb) || (animator.clip == climb && animator.finished)
+                });
+
+                if climbed {
+                    // Stand on top of the corner
+                    let half_extents = half_extents(collider);
+                    let standing = grab.corner + Vec2::new(side * (half_extents.x + 1.), half_extents.y + 1.);
+                    move_to(&mut transform, global_transform, standing);
+                    let_go(&mut commands, &mut gravity_scale);
+                    requests.send(PlayerStateRequest { entity, state: PlayerState::Idle });
+                }
+            }
+            // Knocked off the ledge by something else
+            _ => let_go(&mut commands, &mut gravity_scale),
+        }
+    }
+}
+
+pub fn tick_ledge_cooldown(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    mut query: Query<(Entity, &mut LedgeCooldown)>,
+) {
+    for (entity, mut cooldown) in &mut query {
+        if cooldown.tick(fixed_time.period).finished() {
+            commands.entity(entity).remove::<LedgeCooldown>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 32x32 block with its top left corner at (0, 32), to the right of a player whose hands reach for it
+    const BLOCK: (Vec2, Vec2) = (Vec2::new(16., 16.), Vec2::new(16., 16.));
+
+    #[test]
+    fn finds_a_corner_just_below_the_hands() {
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(-1., 40.), 1), Some(Vec2::new(0., 32.)));
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(2., 32.), 1), Some(Vec2::new(0., 32.)));
+    }
+
+    #[test]
+    fn corners_out_of_reach_are_ignored() {
+        // Above the hands
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(0., 30.), 1), None);
+        // Too far below
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(0., 32. + GRAB_REACH + 1.), 1), None);
+        // Too far to the side
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(-6., 36.), 1), None);
+    }
+
+    #[test]
+    fn uses_the_corner_on_the_players_side() {
+        // Reaching left grabs the top right corner
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(33., 40.), -1), Some(Vec2::new(32., 32.)));
+        assert_eq!(find_corner(&[BLOCK], Vec2::new(-1., 40.), -1), None);
+    }
+
+    #[test]
+    fn corners_with_a_wall_on_top_cant_be_grabbed() {
+        let on_top = (Vec2::new(16., 48.), Vec2::new(16., 16.));
+        assert_eq!(find_corner(&[BLOCK, on_top], Vec2::new(-1., 40.), 1), None);
+
+        // A wall above the player's side doesn't block climbing on to the corner
+        let beside = (Vec2::new(-16., 48.), Vec2::new(16., 16.));
+        assert_eq!(find_corner(&[BLOCK, beside], Vec2::new(-1., 40.), 1), Some(Vec2::new(0., 32.)));
+    }
+}
@@ -1,38 +1,70 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, sprite::Anchor};
 use bevy_ecs_ldtk::prelude::*;
-use std::collections::HashMap;
+use bevy_rapier2d::prelude::Velocity;
 use crate::SHOW_COLLIDER_BOXES;
-use crate::physics::{ColliderBundle, GroundDetection};
+use crate::animation::{AnimationManifest, Animator, animate_sprites, build_texture_atlas, resolve_clips};
+use crate::combat::{DamageTaken, Health, Hurtbox};
+use crate::level::AbilityPickup;
+use crate::physics::{CeilingDetection, ClimbDetection, ColliderBundle, CrouchCollider, GroundDetection, WallDetection};
 
-use crate::{AppState, loading::RegisterAssetEvent};
+use crate::{AddTickEvent, AppState, TickSet, loading::RegisterAssetEvent};
 
 use movement::movement;
+pub use attack::{AttackConfig, ComboController};
+pub use cast::{CastConfig, CastController};
+pub use dash::{DashConfig, DashController};
+pub use movement::{JumpConfig, JumpController, MovementConfig};
+pub use state::{PlayerRevived, PlayerState, PlayerStateChanged, PlayerStateRequest};
 
+mod attack;
+mod cast;
+mod dash;
+mod ledge;
 mod movement;
+mod respawn;
+mod state;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Player {
     pub facing: i8,
-    pub state: PlayerState
+    pub state: PlayerState,
+    /// Whether the sword is drawn. Attacks only work with it out.
+    pub armed: bool,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-pub enum PlayerState {
-  #[default]
-  Idle,
-  Walking,
-  // Jumping,
-  // Shooting
+impl Player {
+    /// Name of the animation clip to play, taking the stance into account
+    pub fn animation(&self) -> &'static str {
+        match self.state {
+            PlayerState::Idle if self.armed => "idle-2",
+            state => state.animation(),
+        }
+    }
+}
+
+/// Abilities picked up over the course of the game, everything starts out locked
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct PlayerAbilities {
+    /// Jumps the player can make in mid air before landing again
+    pub air_jumps: u32,
+}
+
+/// Unlocks an ability for every player, e.g. from a pickup or a story trigger
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnlockAbility {
+    AirJumps(u32),
 }
 
 #[derive(Resource, Default)]
 pub struct PlayerSpriteHandles {
     handles: Vec<HandleUntyped>,
+    animations: Handle<AnimationManifest>,
 }
 
 #[derive(Clone, Default, Bundle, LdtkEntity)]
 pub struct PlayerBundle {
     pub player: Player,
+    pub abilities: PlayerAbilities,
     #[sprite_bundle("image/player.png")]
     #[bundle]
     pub sprite_bundle: SpriteBundle,
@@ -40,6 +72,18 @@ pub struct PlayerBundle {
     #[from_entity_instance]
     pub collider_bundle: ColliderBundle,
     pub ground_detection: GroundDetection,
+    pub wall_detection: WallDetection,
+    pub climb_detection: ClimbDetection,
+    #[with(crouch_collider)]
+    pub crouch_collider: CrouchCollider,
+    pub ceiling_detection: CeilingDetection,
+    pub jump_controller: JumpController,
+    pub dash_controller: DashController,
+    pub combo_controller: ComboController,
+    pub cast_controller: CastController,
+    pub hurtbox: Hurtbox,
+    #[with(player_health)]
+    pub health: Health,
     #[worldly]
     pub worldly: Worldly,
     // The whole EntityInstance can be stored directly as an EntityInstance component
@@ -47,26 +91,88 @@ pub struct PlayerBundle {
     entity_instance: EntityInstance,
 }
 
+/// Crouching and sliding shrink the player to fit through one tile high gaps
+fn crouch_collider(_: &EntityInstance) -> CrouchCollider {
+    CrouchCollider {
+        standing_half_extents: Vec2::new(16., 16.),
+        crouching_half_extents: Vec2::new(16., 7.),
+        crouched: false,
+    }
+}
+
+fn player_health(_: &EntityInstance) -> Health {
+    Health::new(5.)
+}
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
  fn build(&self, app: &mut App) {
   app
     .init_resource::<PlayerSpriteHandles>()
+    .init_resource::<JumpConfig>()
+    .init_resource::<MovementConfig>()
+    .init_resource::<DashConfig>()
+    .init_resource::<AttackConfig>()
+    .init_resource::<CastConfig>()
+    .init_resource::<respawn::LastCheckpoint>()
+    .add_tick_event::<PlayerStateChanged>()
+    .add_tick_event::<PlayerStateRequest>()
+    .add_tick_event::<PlayerRevived>()
+    .add_tick_event::<UnlockAbility>()
     // .add_system(spawn_player.in_schedule(OnEnter(AppState::GameRunning)))
     .add_system(load_sprites.in_schedule(OnEnter(AppState::GameLoading)))
+    .add_system(respawn::spawn_screen_fade.in_schedule(OnExit(AppState::GameLoading)))
     .add_system(spawn_animated_player_sprites.in_set(OnUpdate(AppState::GameRunning)))
-    .add_system(animate_sprite.in_set(OnUpdate(AppState::GameRunning)))
-    .add_system(movement.in_set(OnUpdate(AppState::GameRunning)));
+    .add_system(attack::read_attack_input.in_set(OnUpdate(AppState::GameRunning)).before(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(state::update_player_state.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(attack::track_combo.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(cast::launch_spells.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(cast::charge_cast.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(attack::spawn_attack_hitboxes.in_set(OnUpdate(AppState::GameRunning)).after(animate_sprites).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(state::debug_player_state.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(crouch_player.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(animate_player.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).before(animate_sprites).in_schedule(CoreSchedule::FixedUpdate))
+    // Freshly spawned pickups and checkpoints sit at the origin until physics propagates their transforms
+    .add_system(
+      collect_ability_pickups
+        .run_if(in_state(AppState::GameRunning))
+        .in_base_set(TickSet::PostUpdate)
+        .in_schedule(CoreSchedule::FixedUpdate)
+    )
+    .add_system(
+      respawn::touch_checkpoints
+        .run_if(in_state(AppState::GameRunning))
+        .in_base_set(TickSet::PostUpdate)
+        .in_schedule(CoreSchedule::FixedUpdate)
+    )
+    .add_system(unlock_abilities.in_set(OnUpdate(AppState::GameRunning)).before(movement).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(movement.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(ledge::grab_ledges.in_set(OnUpdate(AppState::GameRunning)).after(movement).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(ledge::hang_from_ledges.in_set(OnUpdate(AppState::GameRunning)).after(movement).after(animate_sprites).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(ledge::tick_ledge_cooldown.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(dash::dash.in_set(OnUpdate(AppState::GameRunning)).after(movement).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(attack::plunge.in_set(OnUpdate(AppState::GameRunning)).after(movement).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(dash::fade_dash_ghosts.in_set(OnUpdate(AppState::GameRunning)))
+    .add_system(hurt_player.in_set(OnUpdate(AppState::GameRunning)).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(respawn::start_respawn.in_set(OnUpdate(AppState::GameRunning)).after(state::update_player_state).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(respawn::respawn.in_set(OnUpdate(AppState::GameRunning)).after(movement).after(dash::dash).in_schedule(CoreSchedule::FixedUpdate))
+    .add_system(respawn::fade_screen.in_set(OnUpdate(AppState::GameRunning)));
  }
 }
 
-fn load_sprites(mut commands: Commands, mut rpg_sprite_handles: ResMut<PlayerSpriteHandles>, asset_server: Res<AssetServer>, mut register_asset: EventWriter<RegisterAssetEvent>) {
+fn load_sprites(mut rpg_sprite_handles: ResMut<PlayerSpriteHandles>, asset_server: Res<AssetServer>, mut register_asset: EventWriter<RegisterAssetEvent>) {
     println!("Load level 'main'");
     register_asset.send(
       RegisterAssetEvent::new(asset_server.load_untyped("image/player.png"), "Player sprite")
     );
 
+    let animations = asset_server.load("animations/adventurer.anim.ron");
+    register_asset.send(
+      RegisterAssetEvent::new(animations.clone_untyped(), "Player animations")
+    );
+    rpg_sprite_handles.animations = animations;
+
     let handles = asset_server.load_folder("image/adventurer").unwrap();
 
     handles.iter().for_each(|handle| {
@@ -78,81 +184,27 @@ fn load_sprites(mut commands: Commands, mut rpg_sprite_handles: ResMut<PlayerSpr
     rpg_sprite_handles.handles = handles;
 }
 
-#[derive(Component)]
-struct AnimationIndices {
-  pub indices: HashMap<&'static str, Vec<usize>>,
-  pub current_index: usize
-}
-
-#[derive(Component, Deref, DerefMut)]
-struct AnimationTimer(Timer);
+type UnanimatedPlayer = (With<Player>, With<Sprite>, Without<TextureAtlasSprite>);
 
 // , Without<TextureAtlasSprite>
-pub fn spawn_animated_player_sprites(mut commands: Commands, asset_server: Res<AssetServer>, rpg_sprite_handles: Res<PlayerSpriteHandles>, mut texture_atlases: ResMut<Assets<TextureAtlas>>, mut textures: ResMut<Assets<Image>>,  player_query: Query<Entity, (With<Player>, With<Sprite>, Without<TextureAtlasSprite>)>) {
+pub fn spawn_animated_player_sprites(mut commands: Commands, asset_server: Res<AssetServer>, rpg_sprite_handles: Res<PlayerSpriteHandles>, manifests: Res<Assets<AnimationManifest>>, mut texture_atlases: ResMut<Assets<TextureAtlas>>, mut textures: ResMut<Assets<Image>>,  player_query: Query<Entity, UnanimatedPlayer>) {
 
     if let Ok(player_entity) = player_query.get_single()  {
-        let mut texture_atlas_builder = TextureAtlasBuilder::default();
+        let Some(manifest) = manifests.get(&rpg_sprite_handles.animations) else {
+            warn!("Player animations are not loaded yet");
+            return;
+        };
 
-        for handle in &rpg_sprite_handles.handles {
-            let handle = handle.typed_weak();
-            let Some(texture) = textures.get(&handle) else {
-                warn!("{:?} did not resolve to an `Image` asset.", asset_server.get_handle_path(handle));
-                continue;
-            };
-
-            texture_atlas_builder.add_texture(handle, texture);
-        }
-        
-        let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
+        let texture_atlas = build_texture_atlas(&rpg_sprite_handles.handles, &asset_server, &mut textures).unwrap();
 
         // show how many textures are loaded:
         println!("Loaded {} textures", rpg_sprite_handles.handles.len());
-        
-        let ( mut idles, mut walking ) = rpg_sprite_handles.handles
-            .iter()
-            .fold(( vec![], vec![] ), |(mut idles, mut walking), handle| {
-                if let Some(asset_path) = asset_server.get_handle_path(handle) {
-                    let path = asset_path.path().to_str().unwrap().to_owned();
-                    // let pathstring = path.to_string_lossy();
-                    if path.contains("adventurer-idle-2") {
-                        idles.push((path, handle.clone()));
-                    } else if path.contains("adventurer-run") {
-                        walking.push((path, handle.clone()));
-                    }
-            }
-            (idles, walking)
-        });
-
-            idles.sort_by_key(|(p, _)| p.to_owned());
-            walking.sort_by_key(|(p, _)| p.to_owned());
-
-            let idle_indices: Vec<usize> = idles.iter().map(| (path, handle) | {
-            let pathstring = path;
-            println!("Path: {}", pathstring);
-            texture_atlas.get_texture_index(
-                &handle.clone_weak().typed::<Image>()
-            ).unwrap()
-            }).collect();
-
-            let walking_indices = walking.iter().map(| (path, handle) | {
-            let pathstring = path;
-            println!("Path: {}", pathstring);
-            texture_atlas.get_texture_index(
-                &handle.clone_weak().typed::<Image>()
-            ).unwrap()
-            }).collect();
-
-            let first_idle = idle_indices[0];
-
-            let hash_map = HashMap::from([
-            ("idle", idle_indices),
-            ("walk", walking_indices)
-            ]);
-
-            let animation_indices = AnimationIndices {
-                indices: hash_map,
-                current_index: 0,
-            };
+
+        let clips = resolve_clips(manifest, &texture_atlas, &rpg_sprite_handles.handles, &asset_server);
+
+            let animator = Animator::new(clips, Player::default().animation())
+                .with_source(rpg_sprite_handles.animations.clone(), rpg_sprite_handles.handles.clone());
+            let first_idle = animator.atlas_index().unwrap();
 
             let atlas_handle = texture_atlases.add(texture_atlas);
             commands.entity(player_entity)
@@ -161,8 +213,7 @@ pub fn spawn_animated_player_sprites(mut commands: Commands, asset_server: Res<A
                     TextureAtlasSprite::new(first_idle),
                     atlas_handle,
                     // transform: Transform::from_xyz(100., 0., 0.),
-                    animation_indices,
-                    AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
+                    animator,
                 ));
                 if SHOW_COLLIDER_BOXES { 
                     commands.entity(player_entity).with_children(|player| {
@@ -178,45 +229,112 @@ pub fn spawn_animated_player_sprites(mut commands: Commands, asset_server: Res<A
                 }
     }
 }
-    
-
-fn animate_sprite(
-    time: Res<Time>,
-    mut query: Query<(
-        &Player,
-        &mut AnimationIndices,
-        &mut AnimationTimer,
-        &mut TextureAtlasSprite,
-    )>,
-  ) {
-    for (player, mut indices, mut timer, mut sprite) in &mut query {
-        timer.tick(time.delta());
-        if timer.just_finished() {
-  
-          let active_index_key = if player.state == PlayerState::Walking {
-              "walk"
-          } else {
-              "idle"
-          };
-  
-          let active_index_vec = indices.indices.get(active_index_key).unwrap_or_else( || panic!("No indices found for key: {} in {:?}", active_index_key, indices.indices));
-  
-          let index_len = active_index_vec.len().clone();
-  
-          let current_index = &mut indices.current_index;
-  
-          indices.current_index = if *current_index >= index_len - 1 {
-            0
-          } else {
-            *current_index + 1
-          };
-  
-          let next_index = indices.current_index.clone();
-          let next_sprite_index = indices.indices.get(active_index_key).unwrap().get(next_index).unwrap().clone();
-  
-          sprite.index = next_sprite_index;   
-          sprite.flip_x = player.facing == -1;
-          
+
+/// Turns ability pickups the player touches into unlocks
+fn collect_ability_pickups(
+    mut commands: Commands,
+    mut unlocks: EventWriter<UnlockAbility>,
+    players: Query<&GlobalTransform, With<Player>>,
+    pickups: Query<(Entity, &GlobalTransform, &EntityInstance, &AbilityPickup)>,
+) {
+    for player_transform in &players {
+        let player_position = player_transform.translation().truncate();
+
+        for (entity, pickup_transform, entity_instance, pickup) in &pickups {
+            let position = pickup_transform.translation().truncate();
+            let half_size = Vec2::new(entity_instance.width as f32, entity_instance.height as f32) / 2.;
+
+            if (player_position - position).abs().cmple(half_size).all() {
+                unlocks.send(pickup.0);
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+fn unlock_abilities(mut unlocks: EventReader<UnlockAbility>, mut query: Query<&mut PlayerAbilities>) {
+    for unlock in unlocks.iter() {
+        info!("Unlocked {:?}", unlock);
+        for mut abilities in &mut query {
+            match *unlock {
+                UnlockAbility::AirJumps(count) => abilities.air_jumps = abilities.air_jumps.max(count),
+            }
         }
     }
-  }
\ No newline at end of file
+}
+
+/// Knocks the player into the hurt state, or the dead one once their health runs out
+fn hurt_player(
+    mut damage_taken: EventReader<DamageTaken>,
+    mut requests: EventWriter<PlayerStateRequest>,
+    players: Query<&Health, With<Player>>,
+) {
+    for ev in damage_taken.iter() {
+        let Ok(health) = players.get(ev.entity) else {
+            continue;
+        };
+
+        debug!("Player took {} damage from {:?}, {}/{} left", ev.amount, ev.source, health.current, health.max);
+
+        let state = if ev.fatal { PlayerState::Dead } else { PlayerState::Hurt };
+        requests.send(PlayerStateRequest { entity: ev.entity, state });
+    }
+}
+
+/// Shrinks the collider while crouching or sliding
+fn crouch_player(mut query: Query<(&Player, &mut CrouchCollider), Changed<Player>>) {
+    for (player, mut crouch_collider) in &mut query {
+        let crouched = matches!(player.state, PlayerState::Crouching | PlayerState::Sliding);
+        if crouch_collider.crouched != crouched {
+            crouch_collider.crouched = crouched;
+        }
+    }
+}
+
+type AnimatedPlayer = (
+    Entity,
+    &'static Player,
+    &'static Velocity,
+    &'static CrouchCollider,
+    &'static Handle<TextureAtlas>,
+    &'static mut Animator,
+    &'static mut TextureAtlasSprite,
+);
+
+/// Picks the clip for the player's current state
+fn animate_player(
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut state_changed: EventReader<PlayerStateChanged>,
+    mut query: Query<AnimatedPlayer>,
+) {
+    let changed: Vec<Entity> = state_changed.iter().map(|ev| ev.entity).collect();
+
+    for (entity, player, velocity, crouch_collider, atlas_handle, mut animator, mut sprite) in &mut query {
+        // The body moves down when the collider shrinks, draw the sprite where it would be standing
+        let frame_height = texture_atlases
+            .get(atlas_handle)
+            .and_then(|atlas| atlas.textures.get(sprite.index))
+            .map_or(0., |frame| frame.height());
+        let anchor = if crouch_collider.crouched && frame_height > 0. {
+            Anchor::Custom(Vec2::new(0., -crouch_collider.center_offset() / frame_height))
+        } else {
+            Anchor::Center
+        };
+        if sprite.anchor.as_vec() != anchor.as_vec() {
+            sprite.anchor = anchor;
+        }
+
+        // Entering a state always plays its clip from the start, even when the last state used the same one
+        let clip = player.animation();
+        animator.play(clip);
+        if changed.contains(&entity) && animator.clip == clip {
+            animator.restart();
+        }
+
+        // Hold still on a ladder while not climbing
+        let paused = player.state == PlayerState::Climbing && velocity.linvel == Vec2::ZERO;
+        animator.speed = if paused { 0. } else { 1. };
+
+        sprite.flip_x = player.facing == -1;
+    }
+}
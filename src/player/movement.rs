@@ -1,39 +1,581 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::physics::GroundDetection;
+use crate::{
+    input::{Action, ActionState},
+    physics::{CeilingDetection, GroundDetection, WallDetection},
+};
 
-use super::{Player, PlayerState};
+use super::{Player, PlayerAbilities, PlayerState, PlayerStateRequest};
 
+// How quickly a slide loses speed, in pixels per second squared
+const SLIDE_FRICTION: f32 = 400.;
+
+/// Tuning for horizontal movement. All rates are in pixels per second squared,
+/// so the player moves the same no matter the frame rate.
+#[derive(Resource, Clone, Debug)]
+pub struct MovementConfig {
+    /// Top running speed, in pixels per second
+    pub max_speed: f32,
+    /// Speeding up towards `max_speed` while a direction is held
+    pub acceleration: f32,
+    /// Slowing down once the direction is let go
+    pub deceleration: f32,
+    /// Braking while holding the opposite direction of travel
+    pub turn_acceleration: f32,
+    /// Multiplier for all rates while in the air, 1.0 steers like on the ground
+    pub air_control: f32,
+    /// Speed going up and down ladders, in pixels per second. Sideways is half of this.
+    pub climb_speed: f32,
+    /// Top speed while crouching, in pixels per second
+    pub crawl_speed: f32,
+    /// Top speed is multiplied by this while the sword is drawn
+    pub armed_speed: f32,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            max_speed: 250.,
+            acceleration: 2500.,
+            deceleration: 3000.,
+            turn_acceleration: 5000.,
+            air_control: 0.6,
+            climb_speed: 120.,
+            crawl_speed: 60.,
+            armed_speed: 0.8,
+        }
+    }
+}
+
+impl MovementConfig {
+    /// Advances the horizontal velocity by `delta_seconds`.
+    /// `input` is the held direction, from -1 to 1.
+    pub fn step(&self, velocity: f32, input: f32, on_ground: bool, delta_seconds: f32) -> f32 {
+        let control = if on_ground { 1.0 } else { self.air_control };
+        let target = input.clamp(-1., 1.) * self.max_speed;
+
+        let mut velocity = velocity;
+        let mut remaining = delta_seconds;
+
+        // Turning around brakes down to zero first, then accelerates for the rest of the step.
+        // Splitting the step where the velocity crosses zero keeps the result frame rate independent.
+        if target != 0. && velocity != 0. && velocity.signum() != target.signum() {
+            let turn_rate = self.turn_acceleration * control;
+            let time_to_stop = velocity.abs() / turn_rate;
+
+            if time_to_stop >= remaining {
+                return move_towards(velocity, 0., turn_rate * remaining);
+            }
+
+            velocity = 0.;
+            remaining -= time_to_stop;
+        }
+
+        let rate = if target == 0. || velocity.abs() > target.abs() {
+            self.deceleration
+        } else {
+            self.acceleration
+        };
+
+        move_towards(velocity, target, rate * control * remaining)
+    }
+}
+
+/// Moves `current` towards `target` by at most `max_delta`, without overshooting
+fn move_towards(current: f32, target: f32, max_delta: f32) -> f32 {
+    if (target - current).abs() <= max_delta {
+        target
+    } else {
+        current + (target - current).signum() * max_delta
+    }
+}
+
+/// Tuning for how the player jumps
+#[derive(Resource, Clone, Debug)]
+pub struct JumpConfig {
+    /// Vertical speed at take off, in pixels per second
+    pub jump_velocity: f32,
+    /// Vertical speed is multiplied by this when Space is released on the way up
+    pub jump_cut: f32,
+    /// Below this vertical speed a held jump counts as being at its apex
+    pub apex_threshold: f32,
+    /// Gravity scale around the apex, lower values hang in the air longer
+    pub apex_gravity: f32,
+    /// Gravity scale while falling
+    pub fall_gravity: f32,
+    /// Seconds after running off a ledge during which a jump still counts as grounded
+    pub coyote_time: f32,
+    /// Seconds a jump press is remembered before landing
+    pub jump_buffer: f32,
+    /// Fastest the player slides down a wall they are holding on to
+    pub wall_slide_speed: f32,
+    /// Velocity when kicking off a wall, `x` points away from the wall
+    pub wall_jump_velocity: Vec2,
+    /// Vertical speed of a jump made in mid air, once air jumps are unlocked
+    pub air_jump_velocity: f32,
+}
+
+impl Default for JumpConfig {
+    fn default() -> Self {
+        Self {
+            jump_velocity: 500.,
+            jump_cut: 0.4,
+            apex_threshold: 60.,
+            apex_gravity: 0.5,
+            fall_gravity: 1.6,
+            coyote_time: 0.1,
+            jump_buffer: 0.12,
+            wall_slide_speed: 80.,
+            wall_jump_velocity: Vec2::new(250., 450.),
+            air_jump_velocity: 420.,
+        }
+    }
+}
+
+/// Per player jump bookkeeping, the timers count down to zero
+#[derive(Clone, Debug, Default, Component)]
+pub struct JumpController {
+    /// Time left to jump after leaving the ground
+    pub coyote_timer: f32,
+    /// Time left before a buffered jump press is dropped
+    pub buffer_timer: f32,
+    /// Set while the player is on the way up from a jump that can still be cut short
+    pub rising: bool,
+    /// Jumps left before the player has to touch the ground again
+    pub air_jumps_left: u32,
+}
+
+impl JumpController {
+    /// Feeds the ground state and jump input for this frame,
+    /// returns true if the player should jump now.
+    pub fn update(&mut self, config: &JumpConfig, on_ground: bool, jump_pressed: bool, delta_seconds: f32) -> bool {
+        if on_ground {
+            self.coyote_timer = config.coyote_time;
+        } else {
+            self.coyote_timer = (self.coyote_timer - delta_seconds).max(0.);
+        }
+
+        if jump_pressed {
+            self.buffer_timer = config.jump_buffer;
+        } else {
+            self.buffer_timer = (self.buffer_timer - delta_seconds).max(0.);
+        }
+
+        if self.buffer_timer > 0. && self.coyote_timer > 0. {
+            // Use both up so a single press can't jump twice
+            self.buffer_timer = 0.;
+            self.coyote_timer = 0.;
+            self.rising = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Uses up a buffered jump press regardless of the ground, for jumps that don't need it
+    pub fn take_buffered_jump(&mut self) -> bool {
+        if self.buffer_timer > 0. {
+            self.buffer_timer = 0.;
+            self.rising = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Uses up one of the air jumps for a jump pressed this frame, if there are any left.
+    /// Older buffered presses are left alone, they belong to the landing.
+    pub fn take_air_jump(&mut self, jump_pressed: bool) -> bool {
+        if self.air_jumps_left == 0 || !jump_pressed {
+            return false;
+        }
+
+        self.air_jumps_left -= 1;
+        self.buffer_timer = 0.;
+        self.rising = true;
+        true
+    }
+
+    /// Works out the gravity scale for this frame and cuts the jump short if Space was let go
+    pub fn apply_jump_arc(&mut self, config: &JumpConfig, velocity: &mut Velocity, on_ground: bool, jump_held: bool) -> f32 {
+        if velocity.linvel.y <= 0. {
+            self.rising = false;
+        }
+
+        if self.rising && !jump_held {
+            velocity.linvel.y *= config.jump_cut;
+            self.rising = false;
+        }
+
+        if on_ground {
+            1.0
+        } else if jump_held && velocity.linvel.y.abs() < config.apex_threshold {
+            config.apex_gravity
+        } else if velocity.linvel.y < 0. {
+            config.fall_gravity
+        } else {
+            1.0
+        }
+    }
+}
+
+type MovingPlayer = (
+  Entity,
+  &'static mut Velocity,
+  &'static mut GravityScale,
+  &'static mut Player,
+  &'static mut JumpController,
+  &'static PlayerAbilities,
+  &'static GroundDetection,
+  &'static WallDetection,
+  &'static CeilingDetection,
+);
 
 pub fn movement(
-  input: Res<Input<KeyCode>>,
-  time: Res<Time>,  
-  mut query: Query<(&mut Velocity, &mut Player, &GroundDetection), With<Player>>,
+  actions: Res<ActionState>,
+  fixed_time: Res<FixedTime>,
+  jump_config: Res<JumpConfig>,
+  movement_config: Res<MovementConfig>,
+  mut requests: EventWriter<PlayerStateRequest>,
+  mut query: Query<MovingPlayer>,
 ) {
-  for (mut velocity, mut player, ground_detection) in &mut query {
-      if input.pressed(KeyCode::D) || input.pressed(KeyCode::A) {
-          // D = <- , A = ->
-          let direction = if input.pressed(KeyCode::D) { 1. } else { -1. };
+  for (entity, mut velocity, mut gravity_scale, mut player, mut jump_controller, abilities, ground_detection, wall_detection, ceiling_detection) in &mut query {
+      // The ledge systems hold the player in place, and dashes and plunges move on their own
+      let moved_elsewhere = matches!(
+        player.state,
+        PlayerState::LedgeHanging | PlayerState::LedgeClimbing | PlayerState::Dash | PlayerState::PlungeReady | PlayerState::PlungeFalling
+      );
+      if moved_elsewhere {
+          continue;
+      }
 
-          velocity.linvel.x = direction * 250.;
-          
+      // Analog, so a half pushed stick walks at half speed
+      let direction = if player.state.accepts_movement() { actions.value(Action::MoveX) } else { 0. };
+
+      if direction != 0. {
           player.facing = if direction > 0. { 1 } else { -1 };
-          player.state = PlayerState::Walking;
-      } else {
-          player.state = PlayerState::Idle;
-          let absolute_speed = velocity.linvel.x.abs();
-          if absolute_speed > 0. {
-              velocity.linvel.x = velocity.linvel.x * 0.09 * time.delta_seconds();
-              if absolute_speed < 10.0 {
-                  velocity.linvel.x = 0.0;
-              }
+      }
+
+      match player.state {
+          PlayerState::Sliding => {
+              velocity.linvel.x = move_towards(velocity.linvel.x, 0., SLIDE_FRICTION * fixed_time.period.as_secs_f32());
           }
+          // Leave knockback and wall kicks alone
+          PlayerState::Hurt | PlayerState::WallJumping => (),
+          PlayerState::Climbing => {
+              let climb = actions.direction(Action::MoveY);
+
+              velocity.linvel = Vec2::new(direction * 0.5, climb) * movement_config.climb_speed;
+          }
+          PlayerState::Crouching => {
+              let crawl_speed = movement_config.crawl_speed;
+              velocity.linvel.x = movement_config
+                  .step(velocity.linvel.x, direction, true, fixed_time.period.as_secs_f32())
+                  .clamp(-crawl_speed, crawl_speed);
+          }
+          _ => {
+              // A drawn sword slows the player down
+              let speed = if player.armed { movement_config.armed_speed } else { 1. };
+              velocity.linvel.x = movement_config.step(
+                  velocity.linvel.x,
+                  direction * speed,
+                  ground_detection.on_ground,
+                  fixed_time.period.as_secs_f32(),
+              );
+          }
+      }
+
+      // The ground sensor still reports ground for a moment after take off, which would refill the coyote timer
+      let on_ground = ground_detection.on_ground && velocity.linvel.y <= 0.;
+      if on_ground {
+        jump_controller.air_jumps_left = abilities.air_jumps;
+      }
+
+      // Falling is allowed so that coyote time can kick in after walking off a ledge
+      let can_jump = (player.state.can_jump() || player.state == PlayerState::Falling) && !ceiling_detection.blocked;
+
+      // Hanging on to a ladder counts as standing on something to jump off from
+      let climbing = player.state == PlayerState::Climbing;
+
+      let jump = jump_controller.update(
+        &jump_config,
+        (on_ground || climbing) && player.state.can_jump(),
+        actions.just_pressed(Action::Jump),
+        fixed_time.period.as_secs_f32(),
+      );
+
+      // A ground or coyote jump uses up the press, so it can't turn into an air jump as well
+      let jumped = jump && can_jump;
+      if jumped {
+        velocity.linvel.y = jump_config.jump_velocity;
+
+        // Jumps off the ground leave on their own, ladders and coyote jumps have no take off to notice
+        if climbing || !on_ground {
+          requests.send(PlayerStateRequest { entity, state: PlayerState::Jumping });
+        }
+      } else if climbing {
+        // Ladders hold the player up
+        gravity_scale.0 = 0.;
+        continue;
+      }
+
+      if player.state == PlayerState::WallSliding {
+        let wall = wall_detection.side();
+
+        if wall != 0 && jump_controller.take_buffered_jump() {
+          // Kick off away from the wall
+          velocity.linvel = Vec2::new(-wall as f32 * jump_config.wall_jump_velocity.x, jump_config.wall_jump_velocity.y);
+          player.facing = -wall;
+        } else {
+          velocity.linvel.y = velocity.linvel.y.max(-jump_config.wall_slide_speed);
+        }
       }
 
-      if input.just_pressed(KeyCode::Space) && (ground_detection.on_ground) {
-        velocity.linvel.y = 500.;
+      // A fresh jump press in mid air turns into a somersault
+      let in_air = matches!(
+        player.state,
+        PlayerState::Jumping | PlayerState::Falling | PlayerState::WallJumping | PlayerState::Somersault
+      );
+      if in_air && !on_ground && !jumped && jump_controller.take_air_jump(actions.just_pressed(Action::Jump)) {
+        velocity.linvel.y = jump_config.air_jump_velocity;
+        requests.send(PlayerStateRequest { entity, state: PlayerState::Somersault });
       }
-      
+
+      gravity_scale.0 = jump_controller.apply_jump_arc(
+        &jump_config,
+        &mut velocity,
+        on_ground,
+        actions.pressed(Action::Jump),
+      );
+
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_RATES: [f32; 3] = [30., 60., 144.];
+
+    // 1/6 of a second is the shortest time that is a whole number of frames at 30, 60 and 144 FPS
+    const SIXTH: f32 = 1. / 6.;
+
+    /// Gentle rates so the velocity is still changing after a sixth of a second
+    fn slow_config() -> MovementConfig {
+        MovementConfig {
+            max_speed: 250.,
+            acceleration: 600.,
+            deceleration: 900.,
+            turn_acceleration: 1200.,
+            air_control: 0.5,
+            climb_speed: 120.,
+            crawl_speed: 60.,
+            armed_speed: 0.8,
+        }
+    }
+
+    /// Runs `step` at the given frame rate for `seconds`, holding `input` the whole time
+    fn simulate(config: &MovementConfig, fps: f32, start: f32, input: f32, on_ground: bool, seconds: f32) -> f32 {
+        let frames = (seconds * fps).round() as usize;
+        (0..frames).fold(start, |velocity, _| config.step(velocity, input, on_ground, 1. / fps))
+    }
+
+    fn assert_same_at_every_frame_rate(config: &MovementConfig, start: f32, input: f32, on_ground: bool, seconds: f32) -> f32 {
+        let results: Vec<f32> = FRAME_RATES
+            .iter()
+            .map(|fps| simulate(config, *fps, start, input, on_ground, seconds))
+            .collect();
+
+        for (fps, result) in FRAME_RATES.iter().zip(&results) {
+            assert!(
+                (result - results[0]).abs() < 0.01,
+                "{} fps gave {} but {} fps gave {}",
+                fps, result, FRAME_RATES[0], results[0]
+            );
+        }
+
+        results[0]
+    }
+
+    #[test]
+    fn acceleration_is_frame_rate_independent() {
+        let velocity = assert_same_at_every_frame_rate(&slow_config(), 0., 1., true, SIXTH);
+        assert!((velocity - 100.).abs() < 0.01);
+    }
+
+    #[test]
+    fn reaches_max_speed_and_stays_there() {
+        let config = MovementConfig::default();
+        let velocity = assert_same_at_every_frame_rate(&config, 0., 1., true, 3. * SIXTH);
+        assert_eq!(velocity, config.max_speed);
+    }
+
+    #[test]
+    fn deceleration_is_frame_rate_independent() {
+        let velocity = assert_same_at_every_frame_rate(&slow_config(), 250., 0., true, SIXTH);
+        assert!((velocity - 100.).abs() < 0.01);
+
+        let velocity = assert_same_at_every_frame_rate(&slow_config(), 250., 0., true, 2. * SIXTH);
+        assert_eq!(velocity, 0.);
+    }
+
+    #[test]
+    fn turning_around_is_frame_rate_independent() {
+        // Stops after 0.125 seconds, which is part way through a frame at 30 and 144 FPS
+        let velocity = assert_same_at_every_frame_rate(&slow_config(), 150., -1., true, SIXTH);
+        assert!((velocity + 25.).abs() < 0.01);
+
+        let config = MovementConfig::default();
+        let velocity = assert_same_at_every_frame_rate(&config, -250., 1., true, 3. * SIXTH);
+        assert_eq!(velocity, config.max_speed);
+    }
+
+    #[test]
+    fn air_control_is_frame_rate_independent_and_weaker() {
+        let in_air = assert_same_at_every_frame_rate(&slow_config(), 0., 1., false, SIXTH);
+        let on_ground = assert_same_at_every_frame_rate(&slow_config(), 0., 1., true, SIXTH);
+        assert!((in_air - on_ground / 2.).abs() < 0.01);
+    }
+
+    #[test]
+    fn slows_down_to_max_speed_after_a_boost() {
+        let config = MovementConfig::default();
+        let velocity = assert_same_at_every_frame_rate(&config, 400., 1., true, 3. * SIXTH);
+        assert_eq!(velocity, config.max_speed);
+    }
+
+    const FRAME: f32 = 1. / 60.;
+
+    /// Stands on the ground for a frame, then spends `frames` in the air without pressing jump
+    fn walk_off_ledge(config: &JumpConfig, frames: usize) -> JumpController {
+        let mut jump = JumpController::default();
+        assert!(!jump.update(config, true, false, FRAME));
+        for _ in 0..frames {
+            assert!(!jump.update(config, false, false, FRAME));
+        }
+        jump
+    }
+
+    /// Presses jump in the air, then falls for `frames` before landing
+    fn press_before_landing(config: &JumpConfig, frames: usize) -> bool {
+        let mut jump = JumpController::default();
+        assert!(!jump.update(config, false, true, FRAME));
+        for _ in 0..frames {
+            assert!(!jump.update(config, false, false, FRAME));
+        }
+        jump.update(config, true, false, FRAME)
+    }
+
+    #[test]
+    fn coyote_time_allows_a_late_jump() {
+        let config = JumpConfig::default();
+        // 5 frames after leaving the ground is within the 0.1 second window
+        let mut jump = walk_off_ledge(&config, 4);
+        assert!(jump.update(&config, false, true, FRAME));
+        assert!(jump.rising);
+    }
+
+    #[test]
+    fn coyote_time_runs_out() {
+        let config = JumpConfig::default();
+        let mut jump = walk_off_ledge(&config, 6);
+        assert!(!jump.update(&config, false, true, FRAME));
+    }
+
+    #[test]
+    fn jump_buffer_jumps_on_landing() {
+        assert!(press_before_landing(&JumpConfig::default(), 5));
+    }
+
+    #[test]
+    fn jump_buffer_runs_out() {
+        // 8 frames is past the 0.12 second buffer
+        assert!(!press_before_landing(&JumpConfig::default(), 7));
+    }
+
+    /// A jump on its way up at `speed`
+    fn rising(speed: f32) -> (JumpController, Velocity) {
+        let jump = JumpController { rising: true, ..default() };
+        (jump, Velocity::linear(Vec2::new(0., speed)))
+    }
+
+    #[test]
+    fn releasing_jump_cuts_the_rise_once() {
+        let config = JumpConfig::default();
+        let (mut jump, mut velocity) = rising(400.);
+
+        jump.apply_jump_arc(&config, &mut velocity, false, false);
+        assert_eq!(velocity.linvel.y, 400. * config.jump_cut);
+        assert!(!jump.rising);
+
+        jump.apply_jump_arc(&config, &mut velocity, false, false);
+        assert_eq!(velocity.linvel.y, 400. * config.jump_cut);
+    }
+
+    #[test]
+    fn holding_jump_keeps_the_full_rise() {
+        let config = JumpConfig::default();
+        let (mut jump, mut velocity) = rising(400.);
+
+        assert_eq!(jump.apply_jump_arc(&config, &mut velocity, false, true), 1.0);
+        assert_eq!(velocity.linvel.y, 400.);
+        assert!(jump.rising);
+    }
+
+    #[test]
+    fn releasing_after_the_peak_does_not_cut() {
+        let config = JumpConfig::default();
+        let (mut jump, mut velocity) = rising(-10.);
+
+        jump.apply_jump_arc(&config, &mut velocity, false, false);
+        assert_eq!(velocity.linvel.y, -10.);
+    }
+
+    #[test]
+    fn gravity_follows_the_arc() {
+        let config = JumpConfig::default();
+        let mut jump = JumpController::default();
+        let mut gravity = |speed: f32, on_ground, held| jump.apply_jump_arc(&config, &mut Velocity::linear(Vec2::new(0., speed)), on_ground, held);
+
+        assert_eq!(gravity(20., false, true), config.apex_gravity);
+        // Letting go at the apex drops right away
+        assert_eq!(gravity(-20., false, false), config.fall_gravity);
+        assert_eq!(gravity(-300., false, true), config.fall_gravity);
+        assert_eq!(gravity(300., false, false), 1.0);
+        assert_eq!(gravity(0., true, true), 1.0);
+    }
+
+    #[test]
+    fn one_press_only_jumps_once() {
+        let config = JumpConfig::default();
+        let mut jump = JumpController::default();
+        assert!(jump.update(&config, true, true, FRAME));
+        assert!(!jump.update(&config, true, false, FRAME));
+        assert!(!jump.take_buffered_jump());
+    }
+
+    #[test]
+    fn air_jumps_run_out() {
+        let mut jump = JumpController { air_jumps_left: 2, ..default() };
+
+        assert!(jump.take_air_jump(true));
+        assert!(jump.take_air_jump(true));
+        assert!(!jump.take_air_jump(true));
+        assert_eq!(jump.air_jumps_left, 0);
+    }
+
+    #[test]
+    fn air_jumps_need_a_fresh_press() {
+        let config = JumpConfig::default();
+        let mut jump = JumpController { air_jumps_left: 1, ..default() };
+
+        // Pressed just before landing, the buffer is waiting for the ground
+        jump.update(&config, false, true, FRAME);
+        assert!(!jump.take_air_jump(false));
+        assert_eq!(jump.air_jumps_left, 1);
+
+        // So the landing still gets its jump
+        assert!(jump.update(&config, true, false, FRAME));
+    }
+}
@@ -0,0 +1,312 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ecs_ldtk::{prelude::*, utils::ldtk_pixel_coords_to_translation_pivoted};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    animation::Animator,
+    combat::{Health, Invulnerable},
+    level::Checkpoint,
+    physics::{ClimbDetection, GroundDetection, GroundSensor, WallDetection, WallSensor},
+};
+
+use super::{ledge::move_to, DashController, JumpController, Player, PlayerRevived, PlayerState};
+
+// Seconds the screen takes to fade to black, and the same again to fade back in
+const FADE_TIME: f32 = 0.5;
+
+// Seconds of invulnerability after coming back
+const RESPAWN_INVULNERABILITY: f32 = 1.5;
+
+// Identifier of the level players start in, and come back to when they haven't reached a checkpoint
+const SPAWN_LEVEL: &str = "Spawn";
+
+/// A place to come back to after dying
+#[derive(Clone, Debug)]
+pub struct RespawnPoint {
+    pub level: LevelSelection,
+    pub position: Vec2,
+}
+
+/// The last checkpoint the player touched, if any
+#[derive(Resource, Default)]
+pub struct LastCheckpoint(pub Option<RespawnPoint>);
+
+/// Fades the screen out and back in around moving a dead player to their respawn point
+#[derive(Component)]
+pub struct Respawning {
+    timer: Timer,
+    /// Set once the player has been moved, while the screen fades back in
+    respawned: bool,
+}
+
+/// A full screen black overlay for fading in and out
+#[derive(Component)]
+pub struct ScreenFade;
+
+pub fn spawn_screen_fade(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                ..default()
+            },
+            background_color: Color::rgba(0., 0., 0., 0.).into(),
+            z_index: ZIndex::Global(100),
+            ..default()
+        },
+        ScreenFade,
+    ));
+}
+
+/// Remembers the checkpoint the player last walked through
+pub fn touch_checkpoints(
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    level_selection: Res<LevelSelection>,
+    players: Query<&GlobalTransform, With<Player>>,
+    checkpoints: Query<(&GlobalTransform, &EntityInstance), With<Checkpoint>>,
+) {
+    for player_transform in &players {
+        let player_position = player_transform.translation().truncate();
+
+        for (checkpoint_transform, entity_instance) in &checkpoints {
+            let position = checkpoint_transform.translation().truncate();
+            let half_size = Vec2::new(entity_instance.width as f32, entity_instance.height as f32) / 2.;
+
+            let inside = (player_position - position).abs().cmple(half_size).all();
+            let already_set = last_checkpoint.0.as_ref().is_some_and(|point| point.position == position);
+
+            if inside && !already_set {
+                info!("Reached checkpoint at {:?}", position);
+                last_checkpoint.0 = Some(RespawnPoint { level: level_selection.clone(), position });
+            }
+        }
+    }
+}
+
+/// Where the `Player` entity sits in the LDtk "Spawn" level, in world space
+fn spawn_point(ldtk_asset: &LdtkAsset) -> Option<RespawnPoint> {
+    let level = ldtk_asset.iter_levels().find(|level| level.identifier == SPAWN_LEVEL)?;
+    let player = level
+        .layer_instances
+        .as_ref()?
+        .iter()
+        .flat_map(|layer| layer.entity_instances.iter())
+        .find(|entity_instance| entity_instance.identifier == "Player")?;
+
+    // Same conversions bevy_ecs_ldtk uses for levels spawned with `UseWorldTranslation`
+    let level_position = Vec2::new(
+        level.world_x as f32,
+        (ldtk_asset.world_height() - level.world_y - level.px_hei) as f32,
+    );
+    let position = ldtk_pixel_coords_to_translation_pivoted(
+        player.px,
+        level.px_hei,
+        IVec2::new(player.width, player.height),
+        player.pivot,
+    );
+
+    Some(RespawnPoint {
+        level: LevelSelection::Iid(level.iid.clone()),
+        position: level_position + position,
+    })
+}
+
+/// Where a dead player comes back: the last checkpoint, or else the start of the game
+#[derive(SystemParam)]
+pub struct RespawnPoints<'w, 's> {
+    last_checkpoint: Res<'w, LastCheckpoint>,
+    ldtk_assets: Res<'w, Assets<LdtkAsset>>,
+    ldtk_worlds: Query<'w, 's, &'static Handle<LdtkAsset>>,
+}
+
+impl RespawnPoints<'_, '_> {
+    fn get(&self) -> Option<RespawnPoint> {
+        self.last_checkpoint.0.clone().or_else(|| {
+            self.ldtk_worlds
+                .iter()
+                .find_map(|handle| self.ldtk_assets.get(handle))
+                .and_then(spawn_point)
+        })
+    }
+}
+
+/// Starts the fade once the death animation has played out
+pub fn start_respawn(
+    mut commands: Commands,
+    players: Query<(Entity, &Player, Option<&Animator>), Without<Respawning>>,
+) {
+    for (entity, player, animator) in &players {
+        if player.state == PlayerState::Dead && animator.is_none_or(|animator| animator.finished) {
+            commands.entity(entity).insert(Respawning {
+                timer: Timer::from_seconds(FADE_TIME, TimerMode::Once),
+                respawned: false,
+            });
+        }
+    }
+}
+
+/// Darkens the screen while a player is respawning
+pub fn fade_screen(
+    respawning: Query<&Respawning>,
+    mut fades: Query<&mut BackgroundColor, With<ScreenFade>>,
+) {
+    let darkness = respawning
+        .iter()
+        .map(|respawning| {
+            if respawning.respawned { respawning.timer.percent_left() } else { respawning.timer.percent() }
+        })
+        .fold(0., f32::max);
+
+    for mut fade in &mut fades {
+        if fade.0.a() != darkness {
+            fade.0.set_a(darkness);
+        }
+    }
+}
+
+type RespawningPlayer = (
+    Entity,
+    &'static mut Respawning,
+    &'static mut Transform,
+    &'static GlobalTransform,
+    &'static mut Velocity,
+    &'static mut GravityScale,
+    &'static mut Health,
+    (&'static mut GroundDetection, &'static mut WallDetection, &'static mut ClimbDetection),
+    (&'static mut JumpController, &'static mut DashController),
+);
+
+/// Fades out, moves the player back to the last checkpoint with everything reset, then fades back in.
+/// The level is switched through `LevelSelection`, so the LDtk world stays loaded.
+pub fn respawn(
+    mut commands: Commands,
+    fixed_time: Res<FixedTime>,
+    respawn_points: RespawnPoints,
+    mut level_selection: ResMut<LevelSelection>,
+    mut revivals: EventWriter<PlayerRevived>,
+    mut players: Query<RespawningPlayer>,
+    mut sensors: (Query<&mut GroundSensor>, Query<&mut WallSensor>),
+) {
+    for (entity, mut respawning, mut transform, global_transform, mut velocity, mut gravity_scale, mut health, detection, controllers) in &mut players {
+        // Hold still while the screen is dark and the level spawns in
+        velocity.linvel = Vec2::ZERO;
+        gravity_scale.0 = 0.;
+
+        if !respawning.timer.tick(fixed_time.period).just_finished() {
+            continue;
+        }
+
+        if respawning.respawned {
+            // Faded back in, hand the player back
+            gravity_scale.0 = 1.;
+            revivals.send(PlayerRevived { entity });
+            commands
+                .entity(entity)
+                .remove::<Respawning>()
+                .insert(Invulnerable::new(RESPAWN_INVULNERABILITY, true));
+            continue;
+        }
+
+        let Some(point) = respawn_points.get() else {
+            warn!("Nowhere to respawn the player");
+            continue;
+        };
+
+        info!("Respawning player at {:?}", point.position);
+
+        // The player is `Worldly`, so its transform is relative to the LDtk world rather than the level
+        move_to(&mut transform, global_transform, point.position);
+        *level_selection = point.level;
+        *health = Health::new(health.max);
+
+        // Forget every contact from where the player died
+        let (mut ground_detection, mut wall_detection, mut climb_detection) = detection;
+        *ground_detection = GroundDetection::default();
+        *wall_detection = WallDetection::default();
+        *climb_detection = ClimbDetection::default();
+        let (ground_sensors, wall_sensors) = &mut sensors;
+        for mut sensor in ground_sensors.iter_mut().filter(|sensor| sensor.ground_detection_entity == entity) {
+            sensor.intersecting_ground_entities.clear();
+        }
+        for mut sensor in wall_sensors.iter_mut().filter(|sensor| sensor.wall_detection_entity == entity) {
+            sensor.intersecting_wall_entities.clear();
+        }
+
+        let (mut jump_controller, mut dash_controller) = controllers;
+        *jump_controller = JumpController::default();
+        *dash_controller = DashController::default();
+
+        respawning.respawned = true;
+        respawning.timer.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{player::PlayerStateRequest, testing::TestApp};
+
+    fn checkpoint_position(game: &mut TestApp) -> Option<Vec2> {
+        let mut checkpoints = game.app.world.query_filtered::<&GlobalTransform, With<Checkpoint>>();
+        checkpoints.iter(&game.app.world).next().map(|transform| transform.translation().truncate())
+    }
+
+    #[test]
+    fn dying_brings_the_player_back_to_the_last_checkpoint() {
+        let mut game = TestApp::new();
+        game.tick_until_grounded(300);
+
+        // Over to the checkpoint in Level_1, on the floor that carries on from Spawn
+        let start = game.player_transform().translation.truncate();
+        game.move_player(Vec2::new(-184. - start.x, 0.));
+        *game.app.world.resource_mut::<LevelSelection>() = LevelSelection::Iid(game.level_iid("Level_1"));
+        let mut checkpoint = None;
+        for _ in 0..10 {
+            game.tick(1);
+            checkpoint = checkpoint_position(&mut game);
+            if checkpoint.is_some() {
+                break;
+            }
+        }
+        let checkpoint = checkpoint.expect("Level_1 has a checkpoint");
+
+        game.tick_until_grounded(60);
+        let reached = game.app.world.resource::<LastCheckpoint>().0.clone().expect("The checkpoint wasn't reached");
+        assert_eq!(reached.position, checkpoint);
+        assert_eq!(reached.level, LevelSelection::Iid(game.level_iid("Level_1")));
+
+        let entity = game.app.world.query_filtered::<Entity, With<Player>>().single(&game.app.world);
+        game.move_player(Vec2::new(48., 0.));
+        game.app.world.send_event(PlayerStateRequest { entity, state: PlayerState::Dead });
+        game.tick_until_state(PlayerState::Dead, 2);
+        game.tick_until_state(PlayerState::Idle, 300);
+
+        let position = game.player_transform().translation.truncate();
+        assert!((position.x - checkpoint.x).abs() < 1., "respawned at {} instead of {}", position, checkpoint);
+        assert!(game.app.world.get::<Invulnerable>(entity).is_some());
+        assert!(game.app.world.get::<Respawning>(entity).is_none());
+    }
+    #[test]
+    fn dying_before_any_checkpoint_goes_back_to_the_spawn_point() {
+        let mut game = TestApp::new();
+        game.tick_until_grounded(300);
+        let start = game.player_transform().translation.truncate();
+
+        // Into Level_1, short of its checkpoint
+        game.move_player(Vec2::new(-100. - start.x, 0.));
+        *game.app.world.resource_mut::<LevelSelection>() = LevelSelection::Iid(game.level_iid("Level_1"));
+        game.tick(10);
+        game.tick_until_grounded(60);
+        assert!(game.app.world.resource::<LastCheckpoint>().0.is_none());
+
+        let entity = game.app.world.query_filtered::<Entity, With<Player>>().single(&game.app.world);
+        game.app.world.send_event(PlayerStateRequest { entity, state: PlayerState::Dead });
+        game.tick_until_state(PlayerState::Dead, 2);
+        game.tick_until_state(PlayerState::Idle, 300);
+
+        let position = game.player_transform().translation.truncate();
+        assert!((position - start).length() < 2., "respawned at {} instead of {}", position, start);
+        assert_eq!(game.level_selection(), LevelSelection::Iid(game.level_iid("Spawn")));
+    }
+}
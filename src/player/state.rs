@@ -0,0 +1,547 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    input::{Action, ActionState},
+    physics::{CeilingDetection, ClimbDetection, GroundDetection, WallDetection},
+};
+
+use crate::animation::Animator;
+
+use super::{ComboController, Player};
+
+// Vertical speed above which a grounded player is considered to have taken off.
+// The ground sensor lags a frame behind a jump, so we can't rely on it alone.
+const TAKE_OFF_SPEED: f32 = 1.0;
+
+// Horizontal speed needed to turn a crouch into a slide
+const SLIDE_START_SPEED: f32 = 150.0;
+
+// Below this horizontal speed a slide comes to rest
+const SLIDE_STOP_SPEED: f32 = 20.0;
+
+const SLIDE_BOOST: f32 = 1.3;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum PlayerState {
+    #[default]
+    Idle,
+    Walking,
+    Jumping,
+    /// Jumping again in mid air
+    Somersault,
+    Falling,
+    Landing,
+    Crouching,
+    Sliding,
+    WallSliding,
+    WallJumping,
+    LedgeHanging,
+    LedgeClimbing,
+    Climbing,
+    Dash,
+    Attack1,
+    Attack2,
+    Attack3,
+    AirAttack1,
+    AirAttack2,
+    /// Winding up a downward slam in mid air
+    PlungeReady,
+    PlungeFalling,
+    /// Hitting the ground at the end of a slam
+    PlungeLanding,
+    Casting,
+    /// Holding a cast to charge it up
+    CastCharging,
+    DrawingSword,
+    SheathingSword,
+    Hurt,
+    Dead,
+}
+
+/// Everything the state machine looks at when deciding on the next state
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PlayerStateContext {
+    pub velocity: Vec2,
+    pub on_ground: bool,
+    pub move_x: f32,
+    /// Held up/down direction, 1 for up
+    pub move_y: f32,
+    pub crouch: bool,
+    /// Side of the player that touches a wall, -1 for left, 1 for right and 0 for none
+    pub wall: i8,
+    pub on_ladder: bool,
+    /// Something overhead stops a crouching player from standing up
+    pub ceiling: bool,
+    /// An attack press is waiting to be used
+    pub attack: bool,
+    /// Ground attacks chained so far in the current combo
+    pub combo: u8,
+    /// The cast button was pressed this frame
+    pub cast: bool,
+    /// The cast button is held, keeps a started cast charging
+    pub cast_held: bool,
+    /// The sword is drawn
+    pub armed: bool,
+    /// Draw or sheathe the sword
+    pub toggle_stance: bool,
+    pub animation_finished: bool,
+}
+
+/// Sent whenever a player switches state, after the exit and enter hooks have run
+pub struct PlayerStateChanged {
+    pub entity: Entity,
+    pub from: PlayerState,
+    pub to: PlayerState,
+}
+
+/// Forces a player into a state the state machine can't derive on its own (hurt, dead)
+pub struct PlayerStateRequest {
+    pub entity: Entity,
+    pub state: PlayerState,
+}
+
+/// Brings a dead player back to `Idle`, once respawning is done. Nothing else leaves `Dead`.
+pub struct PlayerRevived {
+    pub entity: Entity,
+}
+
+/// The state a player is forced into this tick, if any. Dying wins over anything else asked for
+/// in the same tick, otherwise the last request does.
+fn requested_state(current: PlayerState, requests: impl Iterator<Item = PlayerState>, revived: bool) -> Option<PlayerState> {
+    if current == PlayerState::Dead {
+        return revived.then_some(PlayerState::Idle);
+    }
+
+    requests.fold(None, |requested, state| match requested {
+        Some(PlayerState::Dead) => requested,
+        _ => Some(state),
+    })
+}
+
+impl PlayerState {
+    /// Name of the animation clip that plays while in this state
+    pub fn animation(&self) -> &'static str {
+        match self {
+            // Swapped for "idle-2" while the sword is drawn, see `Player::animation`
+            PlayerState::Idle => "idle",
+            PlayerState::Walking => "run",
+            PlayerState::Jumping => "jump",
+            PlayerState::Somersault => "smrslt",
+            PlayerState::Falling => "fall",
+            PlayerState::Landing => "stand",
+            PlayerState::Crouching => "crouch",
+            PlayerState::Sliding => "slide",
+            PlayerState::WallSliding => "wall-slide",
+            PlayerState::WallJumping => "crnr-jmp",
+            PlayerState::LedgeHanging => "crnr-grb",
+            PlayerState::LedgeClimbing => "crnr-clmb",
+            PlayerState::Climbing => "ladder-climb",
+            PlayerState::Dash => "slide",
+            PlayerState::Attack1 => "attack1",
+            PlayerState::Attack2 => "attack2",
+            PlayerState::Attack3 => "attack3",
+            PlayerState::AirAttack1 => "air-attack1",
+            PlayerState::AirAttack2 => "air-attack2",
+            PlayerState::PlungeReady => "air-attack3-rdy",
+            PlayerState::PlungeFalling => "air-attack3-loop",
+            PlayerState::PlungeLanding => "air-attack3-end",
+            PlayerState::Casting => "cast",
+            PlayerState::CastCharging => "cast-loop",
+            PlayerState::DrawingSword => "swrd-drw",
+            PlayerState::SheathingSword => "swrd-shte",
+            PlayerState::Hurt => "hurt",
+            PlayerState::Dead => "die",
+        }
+    }
+
+    /// Whether the player can steer horizontally in this state
+    pub fn accepts_movement(&self) -> bool {
+        matches!(
+            self,
+            PlayerState::Idle
+                | PlayerState::Walking
+                | PlayerState::Jumping
+                | PlayerState::Somersault
+                | PlayerState::Falling
+                | PlayerState::Landing
+                | PlayerState::WallSliding
+                | PlayerState::Climbing
+                | PlayerState::Crouching
+                | PlayerState::AirAttack1
+                | PlayerState::AirAttack2
+        )
+    }
+
+    /// Whether this is one of the melee attacks
+    pub fn is_attack(&self) -> bool {
+        matches!(
+            self,
+            PlayerState::Attack1
+                | PlayerState::Attack2
+                | PlayerState::Attack3
+                | PlayerState::AirAttack1
+                | PlayerState::AirAttack2
+                | PlayerState::PlungeReady
+                | PlayerState::PlungeFalling
+                | PlayerState::PlungeLanding
+        )
+    }
+
+    pub fn is_casting(&self) -> bool {
+        matches!(self, PlayerState::Casting | PlayerState::CastCharging)
+    }
+
+    /// Whether the player is allowed to start a jump from this state
+    pub fn can_jump(&self) -> bool {
+        matches!(
+            self,
+            PlayerState::Idle | PlayerState::Walking | PlayerState::Landing | PlayerState::Crouching | PlayerState::Climbing
+        )
+    }
+
+    /// The transition table. Returns `None` when the player should stay in the current state.
+    pub fn next(&self, ctx: &PlayerStateContext) -> Option<PlayerState> {
+        // Grab on to a ladder by pressing up, or down while in the air
+        let grabs_ladder = ctx.on_ladder && (ctx.move_y > 0. || (ctx.move_y < 0. && !ctx.on_ground));
+        let can_grab_ladder = matches!(
+            self,
+            PlayerState::Idle | PlayerState::Walking | PlayerState::Falling | PlayerState::Landing
+        );
+        if grabs_ladder && can_grab_ladder {
+            return Some(PlayerState::Climbing);
+        }
+
+        let in_air = matches!(
+            self,
+            PlayerState::Jumping | PlayerState::Somersault | PlayerState::Falling | PlayerState::WallJumping
+        );
+        if ctx.attack && in_air && !ctx.on_ground {
+            // Holding down turns the attack into a plunge
+            return Some(if ctx.move_y < 0. { PlayerState::PlungeReady } else { PlayerState::AirAttack1 });
+        }
+
+        let next = match self {
+            // Entered and left through requests from the ledge and dash systems
+            PlayerState::Dead | PlayerState::LedgeHanging | PlayerState::LedgeClimbing | PlayerState::Dash => return None,
+            PlayerState::Hurt => {
+                if !ctx.animation_finished {
+                    return None;
+                }
+                PlayerState::airborne_or(ctx, PlayerState::grounded(ctx))
+            }
+            PlayerState::Jumping | PlayerState::Somersault => {
+                if ctx.velocity.y > 0. {
+                    return None;
+                }
+                if ctx.on_ground { PlayerState::Landing } else { PlayerState::Falling }
+            }
+            PlayerState::Falling => {
+                if ctx.on_ground {
+                    PlayerState::Landing
+                } else if PlayerState::holding_wall(ctx) {
+                    PlayerState::WallSliding
+                } else {
+                    return None;
+                }
+            }
+            PlayerState::WallSliding => {
+                if ctx.on_ground {
+                    PlayerState::Landing
+                } else if ctx.velocity.y > TAKE_OFF_SPEED {
+                    PlayerState::WallJumping
+                } else if !PlayerState::holding_wall(ctx) {
+                    PlayerState::Falling
+                } else {
+                    return None;
+                }
+            }
+            PlayerState::Climbing => {
+                if !ctx.on_ladder {
+                    // Climbed off the top or bottom of the ladder
+                    PlayerState::airborne_or(ctx, PlayerState::grounded(ctx))
+                } else if ctx.on_ground && ctx.move_y < 0. {
+                    PlayerState::grounded(ctx)
+                } else {
+                    return None;
+                }
+            }
+            PlayerState::WallJumping => {
+                if ctx.velocity.y > 0. {
+                    return None;
+                }
+                if ctx.on_ground { PlayerState::Landing } else { PlayerState::Falling }
+            }
+            PlayerState::Attack1 | PlayerState::Attack2 | PlayerState::Attack3 => {
+                if !ctx.animation_finished {
+                    return None;
+                }
+                let next = match self {
+                    PlayerState::Attack1 if ctx.attack => PlayerState::Attack2,
+                    PlayerState::Attack2 if ctx.attack => PlayerState::Attack3,
+                    _ => PlayerState::grounded(ctx),
+                };
+                PlayerState::airborne_or(ctx, next)
+            }
+            PlayerState::AirAttack1 | PlayerState::AirAttack2 => {
+                if ctx.on_ground {
+                    PlayerState::Landing
+                } else if !ctx.animation_finished {
+                    return None;
+                } else if *self == PlayerState::AirAttack1 && ctx.attack {
+                    PlayerState::AirAttack2
+                } else {
+                    PlayerState::airborne_or(ctx, PlayerState::Falling)
+                }
+            }
+            PlayerState::PlungeReady => {
+                if ctx.on_ground {
+                    PlayerState::PlungeLanding
+                } else if ctx.animation_finished {
+                    PlayerState::PlungeFalling
+                } else {
+                    return None;
+                }
+            }
+            PlayerState::PlungeFalling => {
+                if !ctx.on_ground {
+                    return None;
+                }
+                PlayerState::PlungeLanding
+            }
+            PlayerState::PlungeLanding => {
+                if !ctx.animation_finished {
+                    return None;
+                }
+                PlayerState::airborne_or(ctx, PlayerState::grounded(ctx))
+            }
+            PlayerState::Casting => {
+                if !ctx.animation_finished {
+                    return None;
+                }
+                let next = if ctx.cast_held { PlayerState::CastCharging } else { PlayerState::grounded(ctx) };
+                PlayerState::airborne_or(ctx, next)
+            }
+            PlayerState::CastCharging => {
+                let next = if ctx.cast_held { PlayerState::CastCharging } else { PlayerState::grounded(ctx) };
+                PlayerState::airborne_or(ctx, next)
+            }
+            PlayerState::DrawingSword | PlayerState::SheathingSword => {
+                if !ctx.animation_finished {
+                    return None;
+                }
+                PlayerState::airborne_or(ctx, PlayerState::grounded(ctx))
+            }
+            PlayerState::Landing if ctx.attack => PlayerState::airborne_or(ctx, PlayerState::ground_attack(ctx.combo)),
+            PlayerState::Landing => {
+                let landed = PlayerState::grounded(ctx);
+                if !ctx.animation_finished && landed == PlayerState::Idle {
+                    PlayerState::airborne_or(ctx, PlayerState::Landing)
+                } else {
+                    PlayerState::airborne_or(ctx, landed)
+                }
+            }
+            PlayerState::Sliding => {
+                let stopped = ctx.velocity.x.abs() < SLIDE_STOP_SPEED || ctx.animation_finished;
+                let next = if !stopped {
+                    PlayerState::Sliding
+                } else if ctx.crouch || ctx.ceiling {
+                    PlayerState::Crouching
+                } else {
+                    PlayerState::grounded(ctx)
+                };
+                PlayerState::airborne_or(ctx, next)
+            }
+            PlayerState::Idle | PlayerState::Walking | PlayerState::Crouching => {
+                let next = if ctx.crouch && *self != PlayerState::Crouching && ctx.velocity.x.abs() >= SLIDE_START_SPEED {
+                    PlayerState::Sliding
+                } else if *self == PlayerState::Crouching && ctx.ceiling {
+                    // No room to stand up
+                    PlayerState::Crouching
+                } else if ctx.attack {
+                    PlayerState::ground_attack(ctx.combo)
+                } else if ctx.cast {
+                    PlayerState::Casting
+                } else if ctx.toggle_stance {
+                    if ctx.armed { PlayerState::SheathingSword } else { PlayerState::DrawingSword }
+                } else {
+                    PlayerState::grounded(ctx)
+                };
+                PlayerState::airborne_or(ctx, next)
+            }
+        };
+
+        if next == *self { None } else { Some(next) }
+    }
+
+    /// Picks between idle, walking and crouching for a player standing on the ground
+    fn grounded(ctx: &PlayerStateContext) -> PlayerState {
+        if ctx.crouch {
+            PlayerState::Crouching
+        } else if ctx.move_x != 0. {
+            PlayerState::Walking
+        } else {
+            PlayerState::Idle
+        }
+    }
+
+    /// The ground attack that follows `combo` chained attacks, starting over after the third
+    fn ground_attack(combo: u8) -> PlayerState {
+        match combo % 3 {
+            1 => PlayerState::Attack2,
+            2 => PlayerState::Attack3,
+            _ => PlayerState::Attack1,
+        }
+    }
+
+    /// Whether the player is pushing against a wall they are touching
+    fn holding_wall(ctx: &PlayerStateContext) -> bool {
+        ctx.wall != 0 && ctx.move_x == ctx.wall as f32
+    }
+
+    /// Overrides a grounded state with jumping/falling if the player has left the ground
+    fn airborne_or(ctx: &PlayerStateContext, grounded: PlayerState) -> PlayerState {
+        if ctx.velocity.y > TAKE_OFF_SPEED {
+            PlayerState::Jumping
+        } else if !ctx.on_ground {
+            PlayerState::Falling
+        } else {
+            grounded
+        }
+    }
+
+    /// Runs once when the player enters this state
+    fn on_enter(&self, velocity: &mut Velocity) {
+        match self {
+            PlayerState::Sliding => velocity.linvel.x *= SLIDE_BOOST,
+            PlayerState::Crouching | PlayerState::Dead => velocity.linvel.x = 0.,
+            PlayerState::Landing => velocity.linvel.y = velocity.linvel.y.min(0.),
+            _ => (),
+        }
+    }
+
+    /// Runs once when the player leaves this state
+    fn on_exit(&self, velocity: &mut Velocity) {
+        // Don't carry any knockback into the next state
+        if *self == PlayerState::Hurt {
+            velocity.linvel.x = 0.;
+        }
+    }
+}
+
+type StatePlayer = (
+    Entity,
+    &'static mut Player,
+    &'static mut Velocity,
+    &'static GroundDetection,
+    Option<&'static WallDetection>,
+    Option<&'static ClimbDetection>,
+    Option<&'static CeilingDetection>,
+    Option<&'static ComboController>,
+    Option<&'static Animator>,
+);
+
+pub fn update_player_state(
+    actions: Res<ActionState>,
+    mut requests: EventReader<PlayerStateRequest>,
+    mut revivals: EventReader<PlayerRevived>,
+    mut state_changed: EventWriter<PlayerStateChanged>,
+    mut query: Query<StatePlayer>,
+) {
+    let requests: Vec<&PlayerStateRequest> = requests.iter().collect();
+    let revived: Vec<Entity> = revivals.iter().map(|revival| revival.entity).collect();
+
+    for (entity, mut player, mut velocity, ground_detection, wall_detection, climb_detection, ceiling_detection, combo, animation) in &mut query {
+        let move_x = actions.direction(Action::MoveX);
+        let move_y = actions.direction(Action::MoveY);
+
+        let ctx = PlayerStateContext {
+            velocity: velocity.linvel,
+            on_ground: ground_detection.on_ground,
+            move_x,
+            move_y,
+            crouch: move_y < 0.,
+            wall: wall_detection.map_or(0, |wall_detection| wall_detection.side()),
+            on_ladder: climb_detection.is_some_and(|climb_detection| climb_detection.on_ladder()),
+            ceiling: ceiling_detection.is_some_and(|ceiling_detection| ceiling_detection.blocked),
+            // Attacks need the sword out
+            attack: player.armed && combo.is_some_and(|combo| combo.buffer_timer > 0.),
+            combo: combo.map_or(0, |combo| combo.chain),
+            // Holding the button on doesn't start another cast once one has gone off
+            cast: actions.just_pressed(Action::Cast),
+            cast_held: actions.pressed(Action::Cast),
+            armed: player.armed,
+            toggle_stance: actions.just_pressed(Action::Stance),
+            animation_finished: animation.is_none_or(|a| a.finished),
+        };
+
+        let requested = requested_state(
+            player.state,
+            requests.iter().filter(|request| request.entity == entity).map(|request| request.state),
+            revived.contains(&entity),
+        );
+
+        if let Some(next) = requested.or_else(|| player.state.next(&ctx)) {
+            let from = player.state;
+            from.on_exit(&mut velocity);
+            next.on_enter(&mut velocity);
+            player.state = next;
+
+            match next {
+                PlayerState::DrawingSword => player.armed = true,
+                PlayerState::SheathingSword => player.armed = false,
+                _ => (),
+            }
+
+            state_changed.send(PlayerStateChanged { entity, from, to: next });
+        }
+    }
+}
+
+/// Logs every state change at debug level, e.g. with `RUST_LOG=jazz=debug`
+pub fn debug_player_state(mut state_changed: EventReader<PlayerStateChanged>) {
+    for ev in state_changed.iter() {
+        debug!("Player {:?}: {:?} -> {:?}", ev.entity, ev.from, ev.to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_cast_charges_while_held_and_ends_on_release() {
+        let pressed = PlayerStateContext { on_ground: true, cast: true, cast_held: true, ..default() };
+        assert_eq!(PlayerState::Idle.next(&pressed), Some(PlayerState::Casting));
+
+        let held = PlayerStateContext { on_ground: true, cast_held: true, animation_finished: true, ..default() };
+        assert_eq!(PlayerState::Casting.next(&held), Some(PlayerState::CastCharging));
+        assert_eq!(PlayerState::CastCharging.next(&held), None);
+
+        let released = PlayerStateContext { on_ground: true, ..default() };
+        assert_eq!(PlayerState::CastCharging.next(&released), Some(PlayerState::Idle));
+    }
+
+    #[test]
+    fn dying_wins_over_other_requests_in_the_same_tick() {
+        let requests = [PlayerState::Dead, PlayerState::Idle, PlayerState::Falling];
+        assert_eq!(requested_state(PlayerState::Dash, requests.into_iter(), false), Some(PlayerState::Dead));
+
+        let requests = [PlayerState::Hurt, PlayerState::Jumping];
+        assert_eq!(requested_state(PlayerState::Idle, requests.into_iter(), false), Some(PlayerState::Jumping));
+    }
+
+    #[test]
+    fn only_a_revival_leaves_dead() {
+        let requests = [PlayerState::Idle, PlayerState::Falling];
+        assert_eq!(requested_state(PlayerState::Dead, requests.into_iter(), false), None);
+        assert_eq!(requested_state(PlayerState::Dead, std::iter::empty(), true), Some(PlayerState::Idle));
+    }
+
+    #[test]
+    fn holding_cast_does_not_start_another_cast() {
+        let held = PlayerStateContext { on_ground: true, cast_held: true, ..default() };
+        assert_eq!(PlayerState::Idle.next(&held), None);
+        assert_eq!(PlayerState::Walking.next(&held), Some(PlayerState::Idle));
+    }
+}
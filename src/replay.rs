@@ -0,0 +1,183 @@
+use std::{
+    fs::{self, File},
+    io::{LineWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{ecs::schedule::common_conditions::any_with_component, prelude::*};
+use bevy_ecs_ldtk::LevelSelection;
+
+use crate::{
+    input::{Action, ActionState, ActionSystem},
+    player::Player,
+    AppState, TickSet,
+};
+
+/// Everything needed to play a run back: where it started and what was held on every tick
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+    /// Iid of the level the run started in
+    pub level: String,
+    /// The actions that weren't at rest, one entry per tick
+    pub ticks: Vec<Vec<(Action, f32)>>,
+}
+
+impl Recording {
+    /// Reads a file written by `Recorder`: the level on the first line, then one line per tick
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut lines = text.lines();
+
+        let level = lines.next().ok_or("The recording is empty")?;
+        let level = ron::from_str(level).map_err(|err| err.to_string())?;
+        let ticks = lines
+            .map(|line| ron::from_str(line).map_err(|err| err.to_string()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { level, ticks })
+    }
+}
+
+/// Writes down the actions of every tick the game runs. Each tick is flushed to the file as it happens,
+/// so quitting, crashing or killing the game all leave a recording that plays back.
+#[derive(Resource)]
+struct Recorder {
+    path: PathBuf,
+    /// Opened once the run starts
+    file: Option<LineWriter<File>>,
+}
+
+/// Feeds a recording's actions back in place of the real input
+#[derive(Resource)]
+pub struct Replayer {
+    recording: Recording,
+    tick: usize,
+}
+
+/// Records with `--record <file>`, replays with `--replay <file>`
+pub struct ReplayPlugin {
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+}
+
+impl ReplayPlugin {
+    pub fn new(record: Option<PathBuf>, replay: Option<PathBuf>) -> Self {
+        Self { record, replay }
+    }
+
+    pub fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let path_after = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|index| args.get(index + 1))
+                .map(PathBuf::from)
+        };
+
+        Self::new(path_after("--record"), path_after("--replay"))
+    }
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let replay = self.replay.as_ref().and_then(|path| match Recording::load(path) {
+            Ok(recording) => Some(recording),
+            Err(err) => {
+                warn!("Couldn't read recording {}, playing normally: {}", path.display(), err);
+                None
+            }
+        });
+
+        if let Some(recording) = replay {
+            info!("Replaying {} recorded ticks", recording.ticks.len());
+            // Replaces the starting level, so the plugin has to come after the level plugins
+            app
+                .insert_resource(LevelSelection::Iid(recording.level.clone()))
+                .insert_resource(Replayer { recording, tick: 0 });
+        } else if let Some(path) = &self.record {
+            info!("Recording to {}", path.display());
+            app.insert_resource(Recorder { path: path.clone(), file: None });
+        }
+
+        // Only ticks of the running game count, so pausing and loading don't end up in the recording.
+        // The level takes a varying number of ticks to spawn, so the run starts once the player is in it.
+        app
+            .add_system(start_recording.run_if(resource_exists::<Recorder>()).in_schedule(OnEnter(AppState::GameRunning)))
+            .add_system(
+                record_actions
+                    .run_if(resource_exists::<Recorder>())
+                    .run_if(in_state(AppState::GameRunning))
+                    .run_if(any_with_component::<Player>())
+                    .in_base_set(TickSet::First)
+                    .after(ActionSystem)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(
+                replay_actions
+                    .run_if(resource_exists::<Replayer>())
+                    .run_if(in_state(AppState::GameRunning))
+                    .run_if(any_with_component::<Player>())
+                    .in_base_set(TickSet::First)
+                    .after(ActionSystem)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            );
+    }
+}
+
+/// A run starts the first time the game runs, and covers every tick after that.
+/// Unpausing enters `GameRunning` again, which changes nothing.
+fn start_recording(mut commands: Commands, mut recorder: ResMut<Recorder>, level_selection: Res<LevelSelection>) {
+    if recorder.file.is_some() {
+        return;
+    }
+
+    let LevelSelection::Iid(level) = level_selection.as_ref() else {
+        warn!("Only levels selected by iid can be recorded, not {:?}", level_selection);
+        commands.remove_resource::<Recorder>();
+        return;
+    };
+
+    let file = File::create(&recorder.path).and_then(|file| {
+        let mut file = LineWriter::new(file);
+        writeln!(file, "{}", ron::to_string(level).map_err(std::io::Error::other)?)?;
+        Ok(file)
+    });
+
+    match file {
+        Ok(file) => recorder.file = Some(file),
+        Err(err) => {
+            warn!("Couldn't record to {}: {}", recorder.path.display(), err);
+            commands.remove_resource::<Recorder>();
+        }
+    }
+}
+
+fn record_actions(mut commands: Commands, mut recorder: ResMut<Recorder>, actions: Res<ActionState>) {
+    let Recorder { path, file } = recorder.as_mut();
+    let Some(file) = file else {
+        return;
+    };
+
+    let tick: Vec<(Action, f32)> = actions.values().collect();
+    let written = ron::to_string(&tick)
+        .map_err(std::io::Error::other)
+        .and_then(|line| writeln!(file, "{}", line));
+
+    if let Err(err) = written {
+        warn!("Stopped recording to {}: {}", path.display(), err);
+        commands.remove_resource::<Recorder>();
+    }
+}
+
+fn replay_actions(mut commands: Commands, mut replayer: ResMut<Replayer>, mut actions: ResMut<ActionState>) {
+    if let Some(tick) = replayer.recording.ticks.get(replayer.tick) {
+        actions.replace(tick.iter().copied().collect());
+        replayer.tick += 1;
+    }
+
+    // Hands back to the real input right after the last recorded tick
+    if replayer.tick == replayer.recording.ticks.len() {
+        info!("Replay finished after {} ticks", replayer.tick);
+        commands.remove_resource::<Replayer>();
+    }
+}
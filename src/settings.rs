@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+
+use crate::{
+    input::{Action, InputBindings, InputSource, MenuActions},
+    AppState,
+};
+
+// One line per rebindable action and direction. Pause stays on Escape and Start so the menu can always be left.
+const ROWS: [(&str, Action, f32); 9] = [
+    ("Move left", Action::MoveX, -1.),
+    ("Move right", Action::MoveX, 1.),
+    ("Up", Action::MoveY, 1.),
+    ("Down", Action::MoveY, -1.),
+    ("Jump", Action::Jump, 1.),
+    ("Attack", Action::Attack, 1.),
+    ("Cast", Action::Cast, 1.),
+    ("Dash", Action::Dash, 1.),
+    ("Draw / sheathe", Action::Stance, 1.),
+];
+
+// The last line, after every action
+const RESET_ROW: usize = ROWS.len();
+
+/// Which line of the settings screen is selected, and whether it's waiting for a new key or button
+#[derive(Resource, Default)]
+pub struct SettingsMenu {
+    selected: usize,
+    capturing: bool,
+}
+
+impl SettingsMenu {
+    pub fn capturing(&self) -> bool {
+        self.capturing
+    }
+}
+
+// marker for Settings Screen stuff
+#[derive(Component)]
+struct SettingsScreenComponent;
+
+#[derive(Component)]
+struct SettingsRow(usize);
+
+/// Systems that read input while the settings screen is up
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SettingsSystem;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SettingsMenu>()
+            .add_system(create_settings_screen.in_schedule(OnEnter(AppState::GamePaused)))
+            .add_system(rebind_controls.in_set(OnUpdate(AppState::GamePaused)).in_set(SettingsSystem))
+            .add_system(show_settings.in_set(OnUpdate(AppState::GamePaused)).after(rebind_controls))
+            .add_system(destroy_settings_screen.in_schedule(OnExit(AppState::GamePaused)));
+    }
+}
+
+fn create_settings_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut menu: ResMut<SettingsMenu>,
+) {
+    *menu = SettingsMenu::default();
+
+    let font = asset_server.load("fonts/roboto.ttf");
+    let text_style = |font_size| TextStyle { font: font.clone(), font_size, color: Color::WHITE };
+
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: Color::rgba(0., 0., 0., 0.8).into(),
+        // Below the respawn fade
+        z_index: ZIndex::Global(50),
+        ..default()
+    }, SettingsScreenComponent))
+    .with_children(|root| {
+        root.spawn(TextBundle::from_section("Paused", text_style(48.0)));
+        root.spawn(TextBundle::from_section(
+            "Up / down to choose, Enter or Jump to rebind, Escape to resume",
+            text_style(16.0),
+        ));
+
+        for row in 0..=RESET_ROW {
+            root.spawn((TextBundle::from_section("", text_style(24.0)), SettingsRow(row)));
+        }
+    });
+}
+
+fn destroy_settings_screen(
+    mut commands: Commands,
+    mut menu: ResMut<SettingsMenu>,
+    query: Query<Entity, With<SettingsScreenComponent>>,
+) {
+    *menu = SettingsMenu::default();
+
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Moves through the list, and binds the next key or gamepad button pressed to the selected action
+fn rebind_controls(
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    actions: Res<MenuActions>,
+    mut menu: ResMut<SettingsMenu>,
+    mut bindings: ResMut<InputBindings>,
+) {
+    if menu.capturing {
+        // Escape or Start backs out instead of being bound
+        if actions.just_pressed(Action::Pause) {
+            menu.capturing = false;
+            return;
+        }
+
+        let pressed = keys
+            .get_just_pressed()
+            .map(|key| InputSource::Key(*key))
+            .chain(buttons.get_just_pressed().map(|button| InputSource::GamepadButton(button.button_type)))
+            .find(|source| !bindings.is_reserved(*source));
+
+        if let Some(source) = pressed {
+            let (name, action, scale) = ROWS[menu.selected];
+            info!("Binding {:?} to {}", source, name);
+            if bindings.rebind(action, scale, source) {
+                bindings.save();
+            }
+            menu.capturing = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::MoveY) {
+        // Up the screen is towards the first line
+        menu.selected = if actions.direction(Action::MoveY) > 0. {
+            menu.selected.checked_sub(1).unwrap_or(RESET_ROW)
+        } else {
+            (menu.selected + 1) % (RESET_ROW + 1)
+        };
+    }
+
+    if keys.just_pressed(KeyCode::Return) || actions.just_pressed(Action::Jump) {
+        if menu.selected == RESET_ROW {
+            info!("Resetting input bindings");
+            *bindings = InputBindings::default();
+            bindings.save();
+        } else {
+            menu.capturing = true;
+        }
+    }
+}
+
+fn show_settings(
+    menu: Res<SettingsMenu>,
+    bindings: Res<InputBindings>,
+    mut rows: Query<(&SettingsRow, &mut Text)>,
+) {
+    if !menu.is_changed() && !bindings.is_changed() {
+        return;
+    }
+
+    for (row, mut text) in &mut rows {
+        let selected = row.0 == menu.selected;
+
+        let line = if row.0 == RESET_ROW {
+            "Reset to defaults".to_string()
+        } else if selected && menu.capturing {
+            format!("{}: press a key or button...", ROWS[row.0].0)
+        } else {
+            let (name, action, scale) = ROWS[row.0];
+            let sources: Vec<String> = bindings.sources(action, scale).map(source_name).collect();
+            format!("{}: {}", name, sources.join(", "))
+        };
+
+        let section = &mut text.sections[0];
+        section.value = if selected { format!("> {} <", line) } else { line };
+        section.style.color = if selected { Color::YELLOW } else { Color::WHITE };
+    }
+}
+
+fn source_name(source: InputSource) -> String {
+    match source {
+        InputSource::Key(key) => format!("{:?}", key),
+        InputSource::GamepadButton(button) => format!("Pad {:?}", button),
+        InputSource::GamepadAxis(axis) => format!("Pad {:?}", axis),
+    }
+}
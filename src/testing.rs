@@ -0,0 +1,488 @@
+//! Runs the real game plugins without a window or a GPU, for gameplay tests
+
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    ecs::schedule::apply_system_buffers,
+    input::{keyboard::KeyboardInput, ButtonState, InputPlugin},
+    prelude::*,
+    render::texture::ImagePlugin,
+    time::TimeUpdateStrategy,
+};
+use bevy_ecs_ldtk::{
+    app::{LdtkEntityMap, LdtkIntCellMap},
+    prelude::*,
+    systems, LdtkLevelLoader, LdtkLoader, LdtkPlugin, LdtkSystemSet,
+};
+use bevy_rapier2d::prelude::{Collider, RapierConfiguration, RigidBody, Velocity};
+
+use crate::{
+    animation, combat, input, level, loading, physics, replay,
+    level::Wall,
+    physics::GroundDetection,
+    player::{self, Player, PlayerState},
+    AppState, FixedTickPlugin, TIME_STEP,
+};
+
+// Updates to wait for the map and sprites to load before giving up
+const MAX_LOADING_UPDATES: usize = 5000;
+
+/// The same assets and systems as `LdtkPlugin`, without `bevy_ecs_tilemap`'s rendering,
+/// which needs a GPU to set up
+struct HeadlessLdtkPlugin;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, SystemSet)]
+enum HeadlessLdtkSet {
+    PreClean,
+    Clean,
+}
+
+impl Plugin for HeadlessLdtkPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .configure_set(LdtkSystemSet::ProcessApi.after(CoreSet::UpdateFlush).before(CoreSet::PostUpdate))
+            .configure_sets((HeadlessLdtkSet::PreClean, HeadlessLdtkSet::Clean).chain().in_base_set(LdtkSystemSet::ProcessApi))
+            .init_non_send_resource::<LdtkEntityMap>()
+            .init_non_send_resource::<LdtkIntCellMap>()
+            .init_resource::<LdtkSettings>()
+            .init_resource::<ClearColor>()
+            .add_asset::<LdtkAsset>()
+            .init_asset_loader::<LdtkLoader>()
+            .add_asset::<LdtkLevel>()
+            .init_asset_loader::<LdtkLevelLoader>()
+            .add_event::<LevelEvent>()
+            .add_systems((systems::process_ldtk_assets, systems::process_ldtk_levels).in_base_set(CoreSet::PreUpdate))
+            .add_system(systems::worldly_adoption.in_set(HeadlessLdtkSet::PreClean))
+            .add_systems((systems::apply_level_selection, systems::apply_level_set).chain().in_set(HeadlessLdtkSet::PreClean))
+            .add_systems((apply_system_buffers, systems::clean_respawn_entities).chain().in_set(HeadlessLdtkSet::Clean))
+            .add_system(
+                systems::detect_level_spawned_events
+                    .pipe(systems::fire_level_transformed_events)
+                    .in_base_set(CoreSet::PostUpdate),
+            );
+    }
+}
+
+/// The game with `main.ldtk` loaded and the player spawned, stepped one fixed tick at a time
+pub struct TestApp {
+    pub app: App,
+    now: Instant,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::build(|_| ())
+    }
+
+    /// The game with one more plugin, added after all of the game's own
+    pub fn with_plugin(plugin: impl Plugin) -> Self {
+        Self::build(|app| {
+            app.add_plugin(plugin);
+        })
+    }
+
+    fn build(setup: impl FnOnce(&mut App)) -> Self {
+        let mut app = App::new();
+
+        app
+            .add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(InputPlugin)
+            // Assets are read from the crate's `assets` folder, the same as `cargo run`
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(ImagePlugin::default())
+            .add_asset::<TextureAtlas>()
+            .add_state::<AppState>()
+            .add_plugin(FixedTickPlugin)
+            // Not whatever the developer rebound their controls to
+            .insert_resource(input::InputBindings::default())
+            .add_plugin(input::ActionPlugin)
+            .add_plugin(animation::AnimationPlugin)
+            .add_plugins(level::LevelPluginGroup.build().disable::<LdtkPlugin>().add_before::<level::LevelPlugin, _>(HeadlessLdtkPlugin))
+            .add_plugins(physics::PhysicsPluginGroup)
+            .add_plugin(loading::LoadingPlugin)
+            .add_plugin(player::PlayerPlugin)
+            .add_plugin(combat::CombatPlugin);
+        setup(&mut app);
+
+        let mut test_app = Self { app, now: Instant::now() };
+        test_app.load();
+        test_app
+    }
+
+    /// Runs updates until the loading screen hands over and the player is in the level.
+    /// The clock stops once it is, so the player's first tick is the same every run.
+    fn load(&mut self) {
+        for _ in 0..MAX_LOADING_UPDATES {
+            if !self.loaded() {
+                self.now += Duration::from_secs_f32(TIME_STEP);
+            }
+            self.app.insert_resource(TimeUpdateStrategy::ManualInstant(self.now));
+            self.app.update();
+
+            if self.loaded() {
+                return;
+            }
+
+            // Give the asset loading threads a moment
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        panic!("The level didn't load in {} updates", MAX_LOADING_UPDATES);
+    }
+
+    fn loaded(&mut self) -> bool {
+        let running = self.app.world.resource::<State<AppState>>().0 == AppState::GameRunning;
+        let has_player = self.app.world.query_filtered::<(), With<Player>>().iter(&self.app.world).next().is_some();
+        running && has_player
+    }
+
+    /// Steps the game by `ticks` updates of exactly `TIME_STEP`, each running one fixed tick
+    pub fn tick(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.now += Duration::from_secs_f32(TIME_STEP);
+            self.app.insert_resource(TimeUpdateStrategy::ManualInstant(self.now));
+            self.app.update();
+        }
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+        self.send_key(key, ButtonState::Pressed);
+    }
+
+    pub fn release(&mut self, key: KeyCode) {
+        self.send_key(key, ButtonState::Released);
+    }
+
+    fn send_key(&mut self, key: KeyCode, state: ButtonState) {
+        self.app.world.send_event(KeyboardInput { scan_code: 0, key_code: Some(key), state });
+    }
+
+    pub fn player_transform(&mut self) -> Transform {
+        *self.app.world.query_filtered::<&Transform, With<Player>>().single(&self.app.world)
+    }
+
+    pub fn player_state(&mut self) -> PlayerState {
+        self.app.world.query::<&Player>().single(&self.app.world).state
+    }
+
+    pub fn player_half_extents(&mut self) -> Vec2 {
+        let collider = self.app.world.query_filtered::<&Collider, With<Player>>().single(&self.app.world);
+        collider.as_cuboid().expect("The player's collider is a cuboid").half_extents()
+    }
+
+    /// Moves the player by `offset` and stops it
+    pub fn move_player(&mut self, offset: Vec2) {
+        let (mut transform, mut velocity) = self
+            .app
+            .world
+            .query_filtered::<(&mut Transform, &mut Velocity), With<Player>>()
+            .single_mut(&mut self.app.world);
+        transform.translation += offset.extend(0.);
+        velocity.linvel = Vec2::ZERO;
+    }
+
+    /// Adds a solid wall rectangle outside of any level, the way `spawn_wall_collision` spawns them
+    pub fn spawn_wall(&mut self, center: Vec2, half_size: Vec2) {
+        self.app.world.spawn((
+            Collider::cuboid(half_size.x, half_size.y),
+            RigidBody::Fixed,
+            Wall { width: half_size.x * 2., height: half_size.y * 2. },
+            TransformBundle::from_transform(Transform::from_translation(center.extend(0.))),
+        ));
+    }
+
+    pub fn jump_controller(&mut self) -> player::JumpController {
+        self.app.world.query_filtered::<&player::JumpController, With<Player>>().single(&self.app.world).clone()
+    }
+
+    pub fn set_air_jumps(&mut self, air_jumps: u32) {
+        self.app.world.query::<&mut player::PlayerAbilities>().single_mut(&mut self.app.world).air_jumps = air_jumps;
+    }
+
+    pub fn player_velocity(&mut self) -> Vec2 {
+        self.app.world.query_filtered::<&Velocity, With<Player>>().single(&self.app.world).linvel
+    }
+
+    pub fn ground_detection(&mut self) -> GroundDetection {
+        self.app.world.query_filtered::<&GroundDetection, With<Player>>().single(&self.app.world).clone()
+    }
+
+    pub fn level_selection(&self) -> LevelSelection {
+        self.app.world.resource::<LevelSelection>().clone()
+    }
+
+    /// Iid of the level called `identifier` in `main.ldtk`
+    pub fn level_iid(&self, identifier: &str) -> String {
+        let ldtk_assets = self.app.world.resource::<Assets<LdtkAsset>>();
+        let (_, ldtk_asset) = ldtk_assets.iter().next().expect("main.ldtk isn't loaded");
+        let level = ldtk_asset.iter_levels().find(|level| level.identifier == identifier).expect("No such level");
+        level.iid.clone()
+    }
+
+    /// Ticks until the player is in `state`, at most `max_ticks`
+    pub fn tick_until_state(&mut self, state: PlayerState, max_ticks: usize) {
+        for _ in 0..max_ticks {
+            self.tick(1);
+            if self.player_state() == state {
+                return;
+            }
+        }
+
+        panic!("The player didn't get to {:?} within {} ticks, it's {:?}", state, max_ticks, self.player_state());
+    }
+
+    /// Ticks until the player stands on something, at most `max_ticks`
+    pub fn tick_until_grounded(&mut self, max_ticks: usize) {
+        for _ in 0..max_ticks {
+            self.tick(1);
+            if self.ground_detection().on_ground && self.player_velocity().y.abs() < 1. {
+                return;
+            }
+        }
+
+        panic!("The player didn't land within {} ticks", max_ticks);
+    }
+}
+
+#[test]
+fn player_lands_on_the_floor_of_spawn() {
+    let mut game = TestApp::new();
+    let start = game.player_transform().translation;
+
+    game.tick_until_grounded(300);
+    let landed = game.player_transform().translation;
+
+    // The player is placed standing on the floor, so it only settles into the contact
+    assert!((landed - start).length() < 2., "landed at {} instead of {}", landed, start);
+    game.tick(60);
+    assert!(game.ground_detection().on_ground);
+    assert!((game.player_transform().translation.y - landed.y).abs() < 1.);
+    assert_eq!(game.level_selection(), LevelSelection::Iid(game.level_iid("Spawn")));
+}
+
+#[test]
+fn holding_a_direction_walks_that_way() {
+    let mut game = TestApp::new();
+    game.tick_until_grounded(300);
+    let start = game.player_transform().translation;
+
+    game.press(KeyCode::D);
+    game.tick(30);
+    game.release(KeyCode::D);
+    let right = game.player_transform().translation;
+    assert!(right.x > start.x + 10., "{} didn't move right of {}", right.x, start.x);
+
+    game.tick(30);
+    game.press(KeyCode::A);
+    game.tick(30);
+    game.release(KeyCode::A);
+    assert!(game.player_transform().translation.x < right.x);
+    assert!(game.ground_detection().on_ground);
+}
+
+#[test]
+fn jumping_leaves_the_ground_and_comes_back_down() {
+    let mut game = TestApp::new();
+    game.tick_until_grounded(300);
+    let floor = game.player_transform().translation.y;
+
+    game.press(KeyCode::Space);
+    game.tick(10);
+    assert!(!game.ground_detection().on_ground);
+    assert!(game.player_transform().translation.y > floor + 10.);
+
+    game.release(KeyCode::Space);
+    game.tick_until_grounded(300);
+    assert!((game.player_transform().translation.y - floor).abs() < 1.);
+}
+
+#[test]
+fn falling_past_a_ledge_grabs_it_and_climbs_up() {
+    let mut game = TestApp::new();
+    game.tick_until_grounded(300);
+    let start = game.player_transform().translation.truncate();
+    let half_extents = game.player_half_extents();
+
+    // A block standing on the floor right of the player, with its top corner a little above the player's head
+    let corner = start + Vec2::new(half_extents.x + 1., half_extents.y + 30.);
+    let block_half_size = Vec2::new(16., half_extents.y + 15.);
+    game.spawn_wall(corner + Vec2::new(block_half_size.x, -block_half_size.y), block_half_size);
+
+    // Drop the player from just above the corner, so the hands pass it on the way down
+    game.move_player(Vec2::new(0., corner.y - start.y - half_extents.y + 5.));
+    game.tick_until_state(PlayerState::LedgeHanging, 60);
+    let hanging = game.player_transform().translation;
+    assert!((hanging.y + half_extents.y - corner.y).abs() < 8., "hanging at {} from the corner at {}", hanging, corner);
+
+    // Long enough for the grab animation to finish
+    game.tick(30);
+    assert_eq!(game.player_transform().translation, hanging);
+
+    game.press(KeyCode::W);
+    game.tick(2);
+    game.release(KeyCode::W);
+
+    // The climb plays its animation before the player ends up on top
+    assert_eq!(game.player_state(), PlayerState::LedgeClimbing);
+    assert_eq!(game.player_transform().translation, hanging);
+
+    game.tick_until_state(PlayerState::Idle, 120);
+    let standing = game.player_transform().translation;
+    assert!(standing.x > corner.x && standing.y > corner.y, "ended up at {} after climbing {}", standing, corner);
+
+    game.tick_until_grounded(60);
+    assert!((game.player_transform().translation.y - half_extents.y - corner.y).abs() < 2.);
+}
+
+#[test]
+fn walking_into_the_pickup_in_spawn_unlocks_an_air_jump() {
+    let mut game = TestApp::new();
+    game.tick_until_grounded(300);
+    let abilities = |game: &mut TestApp| *game.app.world.query::<&player::PlayerAbilities>().single(&game.app.world);
+    assert_eq!(abilities(&mut game).air_jumps, 0);
+
+    game.press(KeyCode::A);
+    for _ in 0..120 {
+        game.tick(1);
+        if abilities(&mut game).air_jumps > 0 {
+            break;
+        }
+    }
+    game.release(KeyCode::A);
+
+    assert_eq!(abilities(&mut game).air_jumps, 1);
+    game.tick(1);
+    assert_eq!(game.app.world.query::<&level::AbilityPickup>().iter(&game.app.world).count(), 0);
+
+    // Jump, then jump again in mid air
+    game.tick_until_grounded(60);
+    game.press(KeyCode::Space);
+    game.tick(1);
+    game.release(KeyCode::Space);
+    game.tick_until_state(PlayerState::Falling, 120);
+    game.press(KeyCode::Space);
+    game.tick_until_state(PlayerState::Somersault, 3);
+    game.release(KeyCode::Space);
+    assert!(game.player_velocity().y > 0.);
+}
+
+#[test]
+fn landing_refills_the_air_jumps() {
+    let mut game = TestApp::new();
+    game.set_air_jumps(2);
+    game.tick_until_grounded(300);
+
+    // Jump, then use up both air jumps
+    game.press(KeyCode::Space);
+    game.tick(1);
+    game.release(KeyCode::Space);
+    game.tick_until_state(PlayerState::Falling, 120);
+    for _ in 0..2 {
+        game.press(KeyCode::Space);
+        game.tick(1);
+        game.release(KeyCode::Space);
+        game.tick(1);
+    }
+    assert_eq!(game.jump_controller().air_jumps_left, 0);
+
+    game.tick_until_grounded(300);
+    assert_eq!(game.jump_controller().air_jumps_left, 2);
+}
+
+#[test]
+fn a_coyote_jump_keeps_the_air_jump() {
+    let mut game = TestApp::new();
+    game.set_air_jumps(1);
+    game.tick_until_grounded(300);
+    let start = game.player_transform().translation.truncate();
+    let half_extents = game.player_half_extents();
+
+    // A narrow platform high above the floor, with the player standing on it
+    let platform_half_size = Vec2::new(half_extents.x + 8., 8.);
+    let feet = start + Vec2::new(0., 96. - half_extents.y);
+    game.spawn_wall(feet - Vec2::new(0., platform_half_size.y), platform_half_size);
+    game.move_player(Vec2::new(0., 96.));
+    game.tick_until_grounded(60);
+
+    // Walk off the edge and jump while the coyote time lasts
+    game.press(KeyCode::D);
+    game.tick_until_state(PlayerState::Falling, 60);
+    game.release(KeyCode::D);
+    game.press(KeyCode::Space);
+    game.tick(1);
+
+    assert_eq!(game.jump_controller().air_jumps_left, 1);
+    // The full jump, less the one tick of gravity since
+    let gravity = game.app.world.resource::<RapierConfiguration>().gravity.y;
+    let expected = player::JumpConfig::default().jump_velocity + gravity * TIME_STEP;
+    assert!((game.player_velocity().y - expected).abs() < 1., "jumped at {} instead of {}", game.player_velocity().y, expected);
+
+    // Plays the jump, not the rest of the fall
+    game.release(KeyCode::Space);
+    game.tick_until_state(PlayerState::Jumping, 3);
+}
+
+#[test]
+fn a_replay_plays_the_run_back_the_same() {
+    let path = std::env::temp_dir().join(format!("jazz-replay-{}.ron", std::process::id()));
+
+    let mut recorded = TestApp::with_plugin(replay::ReplayPlugin::new(Some(path.clone()), None));
+    recorded.press(KeyCode::D);
+    recorded.tick(20);
+    recorded.press(KeyCode::Space);
+    recorded.tick(8);
+    recorded.release(KeyCode::Space);
+    recorded.tick(30);
+    recorded.release(KeyCode::D);
+    recorded.press(KeyCode::A);
+    recorded.tick(15);
+    recorded.release(KeyCode::A);
+    recorded.tick(10);
+    let expected = (recorded.player_transform().translation, recorded.player_velocity(), recorded.player_state());
+
+    // Nothing is pressed this time, the recording does all the moving
+    let mut replayed = TestApp::with_plugin(replay::ReplayPlugin::new(None, Some(path.clone())));
+    for _ in 0..200 {
+        if !replayed.app.world.contains_resource::<replay::Replayer>() {
+            break;
+        }
+        replayed.tick(1);
+    }
+    fs::remove_file(&path).unwrap();
+
+    assert!(!replayed.app.world.contains_resource::<replay::Replayer>(), "The replay didn't finish");
+    assert!(expected.0.x > 100., "The recorded run didn't go anywhere");
+    assert_eq!((replayed.player_transform().translation, replayed.player_state()), (expected.0, expected.2));
+    // Resting contacts leave a little solver noise in the velocity
+    assert!(replayed.player_velocity().abs_diff_eq(expected.1, 1e-3), "Replayed velocity {} instead of {}", replayed.player_velocity(), expected.1);
+}
+
+#[test]
+fn greedy_walls_need_no_more_colliders_than_plates_in_every_level() {
+    let game = TestApp::new();
+    let levels = game.app.world.resource::<Assets<LdtkLevel>>();
+    assert!(levels.len() >= 3, "Only {} levels loaded", levels.len());
+
+    for (_, level) in levels.iter() {
+        let layers = level.level.layer_instances.as_ref().expect("Level asset should have layers");
+        let grid = layers.iter().find(|layer| layer.identifier == "WorldGrid").expect("Level should have a WorldGrid");
+
+        // Both kinds of wall tile, flipped to bottom-up grid coordinates
+        let walls = grid
+            .int_grid_csv
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| matches!(value, 1 | 2))
+            .map(|(i, _)| GridCoords { x: i as i32 % grid.c_wid, y: grid.c_hei - 1 - i as i32 / grid.c_wid })
+            .collect();
+
+        let plates = physics::WallMerge::Plates.merge(&walls, grid.c_wid, grid.c_hei).len();
+        let greedy = physics::WallMerge::Greedy.merge(&walls, grid.c_wid, grid.c_hei).len();
+        assert!(greedy <= plates, "{} has {} greedy colliders and {} plates", level.level.identifier, greedy, plates);
+    }
+}